@@ -0,0 +1,1466 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use crate::disassembler::disassemble;
+
+/// A program assembled from source, ready to be written as an object file or loaded in a VM
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assembled {
+    /// Address of the first word, from `.ORIG`
+    pub origin: u16,
+    pub words: Vec<u16>,
+    /// Address of every label
+    pub symbols: HashMap<String, u16>,
+    /// Addresses emitted by `.FILL`, `.BLKW` and `.STRINGZ` rather than by an instruction
+    pub data: HashSet<u16>,
+    /// Uses of `.EXTERNAL` labels, patched by [`link`]
+    pub relocations: Vec<Relocation>,
+    /// Labels named by `.GLOBL` (or `.GLOBAL`), the only ones other modules can refer to
+    pub exports: HashSet<String>,
+    /// Source line of the instruction at each address
+    pub lines: HashMap<u16, usize>,
+}
+
+/// A word referring to a label defined in another module
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Relocation {
+    pub address: u16,
+    pub label: String,
+    /// Width of the PC offset field to fill, `None` for a `.FILL` taking the whole address
+    pub offset_bits: Option<u32>,
+}
+
+impl Assembled {
+    /// The object file: the origin then every word, big-endian, as read by [`crate::VM::load`]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        crate::words_to_obj(self.origin, &self.words)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AsmError {
+    /// Something other than a comment comes before `.ORIG`
+    MissingOrigin,
+    UnknownMnemonic {
+        line: usize,
+        mnemonic: String,
+    },
+    InvalidOperand {
+        line: usize,
+        operand: String,
+    },
+    OperandCount {
+        line: usize,
+        expected: usize,
+        found: usize,
+    },
+    UndefinedLabel {
+        line: usize,
+        label: String,
+    },
+    DuplicateLabel {
+        line: usize,
+        label: String,
+    },
+    /// The label is too far from the instruction for its offset field
+    OutOfRange {
+        line: usize,
+        label: String,
+    },
+    /// A number doesn't fit its field, e.g. `#16` for an imm5
+    OperandOutOfRange {
+        line: usize,
+        field: &'static str,
+        value: i32,
+        range: RangeInclusive<i32>,
+    },
+    /// The statement at `line` runs the image past xFFFF
+    ImageTooLarge {
+        line: usize,
+    },
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AsmError::MissingOrigin => write!(f, "the program must start with .ORIG"),
+            AsmError::UnknownMnemonic { line, mnemonic } => {
+                write!(f, "line {line}: unknown mnemonic {mnemonic}")
+            }
+            AsmError::InvalidOperand { line, operand } => {
+                write!(f, "line {line}: invalid operand {operand}")
+            }
+            AsmError::OperandCount {
+                line,
+                expected,
+                found,
+            } => write!(
+                f,
+                "line {line}: expected {expected} operands, found {found}"
+            ),
+            AsmError::UndefinedLabel { line, label } => {
+                write!(f, "line {line}: undefined label {label}")
+            }
+            AsmError::DuplicateLabel { line, label } => {
+                write!(f, "line {line}: label {label} is already defined")
+            }
+            AsmError::OutOfRange { line, label } => {
+                write!(f, "line {line}: label {label} is out of range")
+            }
+            AsmError::OperandOutOfRange {
+                line,
+                field,
+                value,
+                range,
+            } => write!(
+                f,
+                "line {line}: {value} doesn't fit {field}, expected {} to {}",
+                range.start(),
+                range.end()
+            ),
+            AsmError::ImageTooLarge { line } => {
+                write!(f, "line {line}: the image runs past the end of memory")
+            }
+        }
+    }
+}
+
+impl Error for AsmError {}
+
+/// The target of a PC-relative offset can't be encoded in the field
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RangeError {
+    pub from: u16,
+    pub to: u16,
+    pub bits: u32,
+}
+
+impl fmt::Display for RangeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "x{:04X} is out of range of a {} bits offset from x{:04X}",
+            self.to, self.bits, self.from
+        )
+    }
+}
+
+impl Error for RangeError {}
+
+/// Offset field of the instruction at `from` reaching `to`, relative to the incremented PC
+fn pcrel(from: u16, to: u16, bits: u32) -> Result<u16, RangeError> {
+    let offset = to as i32 - (from as i32 + 1);
+    let limit = 1 << (bits - 1);
+    if offset < -limit || offset >= limit {
+        return Err(RangeError { from, to, bits });
+    }
+    Ok(offset as u16 & ((1 << bits) - 1))
+}
+
+/// PCoffset9 field (BR, LD, ST, LDI, STI, LEA) of the instruction at `from` reaching `to`
+pub fn pcrel9(from: u16, to: u16) -> Result<u16, RangeError> {
+    pcrel(from, to, 9)
+}
+
+/// PCoffset11 field (JSR) of the instruction at `from` reaching `to`
+pub fn pcrel11(from: u16, to: u16) -> Result<u16, RangeError> {
+    pcrel(from, to, 11)
+}
+
+/// 6 bits offset field of the instruction at `from` reaching `to`
+pub fn pcrel6(from: u16, to: u16) -> Result<u16, RangeError> {
+    pcrel(from, to, 6)
+}
+
+/// Why modules couldn't be linked together
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkError {
+    /// No module exports this `.EXTERNAL` label
+    UnresolvedExternal { label: String },
+    /// Several modules export this label
+    DuplicateSymbol { label: String },
+    /// Two modules both have a word at `address`
+    Overlap { address: u16 },
+    /// The label is too far from the instruction at `address` for its offset field
+    OutOfRange { address: u16, label: String },
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkError::UnresolvedExternal { label } => {
+                write!(f, "unresolved external label {label}")
+            }
+            LinkError::DuplicateSymbol { label } => {
+                write!(f, "label {label} is exported by several modules")
+            }
+            LinkError::Overlap { address } => {
+                write!(f, "several modules are placed at x{address:04X}")
+            }
+            LinkError::OutOfRange { address, label } => {
+                write!(f, "x{address:04X}: label {label} is out of range")
+            }
+        }
+    }
+}
+
+impl Error for LinkError {}
+
+const MNEMONICS: &[&str] = &[
+    "ADD", "AND", "NOT", "JMP", "JSR", "JSRR", "LD", "LDI", "LDR", "LEA", "ST", "STI", "STR",
+    "TRAP", "RTI",
+];
+
+/// Operand-less pseudo-instructions and the word each one stands for
+const PSEUDO_OPS: &[(&str, u16)] = &[
+    ("RET", 0xC1C0),
+    ("NOP", 0x0000),
+    ("GETC", 0xF020),
+    ("OUT", 0xF021),
+    ("PUTS", 0xF022),
+    ("IN", 0xF023),
+    ("PUTSP", 0xF024),
+    ("HALT", 0xF025),
+];
+
+/// A source line split into its label, mnemonic (upper case) and operands
+struct Statement {
+    line: usize,
+    label: Option<String>,
+    mnemonic: Option<String>,
+    operands: Vec<String>,
+}
+
+fn is_mnemonic(token: &str) -> bool {
+    let token = token.to_uppercase();
+    token.starts_with('.')
+        || MNEMONICS.contains(&token.as_str())
+        || PSEUDO_OPS.iter().any(|(name, _)| *name == token)
+        || (token.starts_with("BR") && token[2..].chars().all(|c| "NZP".contains(c)))
+}
+
+/// Remove the comment of a line, a `;` inside a string doesn't start one
+fn strip_comment(text: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match c {
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            ';' if !in_string => return &text[..i],
+            _ => {}
+        }
+    }
+    text
+}
+
+fn parse_statement(line: usize, text: &str) -> Statement {
+    let text = strip_comment(text).trim();
+    let mut statement = Statement {
+        line,
+        label: None,
+        mnemonic: None,
+        operands: Vec::new(),
+    };
+
+    let mut rest = text;
+    let first = rest.split_whitespace().next().unwrap_or_default();
+    if !first.is_empty() && !is_mnemonic(first) {
+        statement.label = Some(first.to_string());
+        rest = rest[first.len()..].trim_start();
+    }
+
+    let mnemonic = rest.split_whitespace().next().unwrap_or_default();
+    if mnemonic.is_empty() {
+        return statement;
+    }
+    statement.mnemonic = Some(mnemonic.to_uppercase());
+    let rest = rest[mnemonic.len()..].trim();
+
+    if mnemonic.eq_ignore_ascii_case(".STRINGZ") {
+        statement.operands.push(rest.to_string());
+    } else {
+        statement.operands = rest
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|s| !s.is_empty())
+            .map(String::from)
+            .collect();
+    }
+    statement
+}
+
+/// Parse `#10`, `#-3`, `x3000` or `10`
+fn parse_number(token: &str) -> Option<i32> {
+    if let Some(decimal) = token.strip_prefix('#') {
+        decimal.parse().ok()
+    } else if let Some(hex) = token.strip_prefix(['x', 'X']) {
+        let (negative, digits) = match hex.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, hex),
+        };
+        let value = i32::from_str_radix(digits, 16).ok()?;
+        Some(if negative { -value } else { value })
+    } else {
+        token.parse().ok()
+    }
+}
+
+/// Decode the escapes of a `.STRINGZ` literal
+fn parse_string(line: usize, token: &str) -> Result<Vec<u16>, AsmError> {
+    let invalid = || AsmError::InvalidOperand {
+        line,
+        operand: token.to_string(),
+    };
+    let inner = token
+        .strip_prefix('"')
+        .and_then(|t| t.strip_suffix('"'))
+        .ok_or_else(invalid)?;
+
+    let mut words = Vec::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        let c = match c {
+            '\\' => match chars.next().ok_or_else(invalid)? {
+                'n' => '\n',
+                't' => '\t',
+                'r' => '\r',
+                '0' => '\0',
+                other => other,
+            },
+            c => c,
+        };
+        words.push(c as u16);
+    }
+    Ok(words)
+}
+
+/// Number of words a statement takes in the image
+fn size(statement: &Statement) -> Result<u32, AsmError> {
+    let line = statement.line;
+    match statement.mnemonic.as_deref() {
+        None => Ok(0),
+        Some(".BLKW") => {
+            let count = statement.operands.first().ok_or(AsmError::OperandCount {
+                line,
+                expected: 1,
+                found: 0,
+            })?;
+            let value = parse_number(count).ok_or_else(|| AsmError::InvalidOperand {
+                line,
+                operand: count.clone(),
+            })?;
+            let range = 0..=0xFFFF;
+            if !range.contains(&value) {
+                return Err(AsmError::OperandOutOfRange {
+                    line,
+                    field: ".BLKW count",
+                    value,
+                    range,
+                });
+            }
+            Ok(value as u32)
+        }
+        Some(".STRINGZ") => Ok(parse_string(line, &statement.operands[0])?.len() as u32 + 1),
+        Some(".END") | Some(".EXTERNAL") | Some(".GLOBL") | Some(".GLOBAL") => Ok(0),
+        Some(_) => Ok(1),
+    }
+}
+
+/// Encoding context of one statement: where it is and which labels are known
+struct Context<'a> {
+    line: usize,
+    pc: u16,
+    symbols: &'a HashMap<String, u16>,
+}
+
+impl Context<'_> {
+    fn invalid(&self, operand: &str) -> AsmError {
+        AsmError::InvalidOperand {
+            line: self.line,
+            operand: operand.to_string(),
+        }
+    }
+
+    fn register(&self, operand: &str) -> Result<u16, AsmError> {
+        let reg = operand
+            .strip_prefix(['R', 'r'])
+            .and_then(|n| n.parse::<u16>().ok())
+            .filter(|n| *n < 8);
+        reg.ok_or_else(|| self.invalid(operand))
+    }
+
+    fn number(&self, operand: &str) -> Result<i32, AsmError> {
+        parse_number(operand).ok_or_else(|| self.invalid(operand))
+    }
+
+    /// A number fitting the two's complement field `field` of `bits` bits
+    fn signed(&self, operand: &str, field: &'static str, bits: u32) -> Result<u16, AsmError> {
+        let value = self.number(operand)?;
+        self.check_range(value, field, -(1 << (bits - 1))..=(1 << (bits - 1)) - 1)?;
+        Ok(value as u16 & ((1 << bits) - 1))
+    }
+
+    fn check_range(
+        &self,
+        value: i32,
+        field: &'static str,
+        range: RangeInclusive<i32>,
+    ) -> Result<(), AsmError> {
+        if range.contains(&value) {
+            Ok(())
+        } else {
+            Err(AsmError::OperandOutOfRange {
+                line: self.line,
+                field,
+                value,
+                range,
+            })
+        }
+    }
+
+    /// A label or a number, the address of a label is turned into an offset from the incremented PC
+    fn pc_offset(&self, operand: &str, bits: u32) -> Result<u16, AsmError> {
+        match parse_number(operand) {
+            Some(_) => {
+                let field = if bits == 9 { "PCoffset9" } else { "PCoffset11" };
+                self.signed(operand, field, bits)
+            }
+            None => pcrel(self.pc, self.label(operand)?, bits).map_err(|_| AsmError::OutOfRange {
+                line: self.line,
+                label: operand.to_string(),
+            }),
+        }
+    }
+
+    fn label(&self, operand: &str) -> Result<u16, AsmError> {
+        self.symbols
+            .get(operand)
+            .copied()
+            .ok_or_else(|| AsmError::UndefinedLabel {
+                line: self.line,
+                label: operand.to_string(),
+            })
+    }
+}
+
+fn expect_operands(line: usize, operands: &[String], expected: usize) -> Result<(), AsmError> {
+    if operands.len() == expected {
+        Ok(())
+    } else {
+        Err(AsmError::OperandCount {
+            line,
+            expected,
+            found: operands.len(),
+        })
+    }
+}
+
+/// Encode one instruction
+fn encode(mnemonic: &str, operands: &[String], ctx: &Context) -> Result<u16, AsmError> {
+    let line = ctx.line;
+    if let Some(&(_, word)) = PSEUDO_OPS.iter().find(|(name, _)| *name == mnemonic) {
+        expect_operands(line, operands, 0)?;
+        return Ok(word);
+    }
+    let word = match mnemonic {
+        "ADD" | "AND" => {
+            expect_operands(line, operands, 3)?;
+            let opcode = if mnemonic == "ADD" { 0b0001 } else { 0b0101 };
+            let dr = ctx.register(&operands[0])?;
+            let sr1 = ctx.register(&operands[1])?;
+            let last = match ctx.register(&operands[2]) {
+                Ok(sr2) => sr2,
+                Err(_) => 1 << 5 | ctx.signed(&operands[2], "imm5", 5)?,
+            };
+            opcode << 12 | dr << 9 | sr1 << 6 | last
+        }
+        "NOT" => {
+            expect_operands(line, operands, 2)?;
+            let dr = ctx.register(&operands[0])?;
+            let sr = ctx.register(&operands[1])?;
+            0b1001 << 12 | dr << 9 | sr << 6 | 0x3F
+        }
+        "JMP" | "JSRR" => {
+            expect_operands(line, operands, 1)?;
+            let opcode = if mnemonic == "JMP" { 0b1100 } else { 0b0100 };
+            opcode << 12 | ctx.register(&operands[0])? << 6
+        }
+        "JSR" => {
+            expect_operands(line, operands, 1)?;
+            0b0100 << 12 | 1 << 11 | ctx.pc_offset(&operands[0], 11)?
+        }
+        "LD" | "LDI" | "LEA" | "ST" | "STI" => {
+            expect_operands(line, operands, 2)?;
+            let opcode = match mnemonic {
+                "LD" => 0b0010,
+                "LDI" => 0b1010,
+                "LEA" => 0b1110,
+                "ST" => 0b0011,
+                _ => 0b1011,
+            };
+            let r = ctx.register(&operands[0])?;
+            opcode << 12 | r << 9 | ctx.pc_offset(&operands[1], 9)?
+        }
+        "LDR" | "STR" => {
+            expect_operands(line, operands, 3)?;
+            let opcode = if mnemonic == "LDR" { 0b0110 } else { 0b0111 };
+            let r = ctx.register(&operands[0])?;
+            let base = ctx.register(&operands[1])?;
+            let offset = ctx.signed(&operands[2], "offset6", 6)?;
+            opcode << 12 | r << 9 | base << 6 | offset
+        }
+        "TRAP" => {
+            expect_operands(line, operands, 1)?;
+            let vector = ctx.number(&operands[0])?;
+            ctx.check_range(vector, "trapvect8", 0..=0xFF)?;
+            0b1111 << 12 | vector as u16
+        }
+        "RTI" => {
+            expect_operands(line, operands, 0)?;
+            0b1000 << 12
+        }
+        br if br.starts_with("BR") => {
+            expect_operands(line, operands, 1)?;
+            let mut nzp = 0;
+            for (flag, bit) in [('N', 0b100), ('Z', 0b010), ('P', 0b001)] {
+                if br[2..].contains(flag) {
+                    nzp |= bit;
+                }
+            }
+            if nzp == 0 {
+                nzp = 0b111;
+            }
+            nzp << 9 | ctx.pc_offset(&operands[0], 9)?
+        }
+        _ => {
+            return Err(AsmError::UnknownMnemonic {
+                line,
+                mnemonic: mnemonic.to_string(),
+            })
+        }
+    };
+    Ok(word)
+}
+
+/// Assemble LC-3 source with `.ORIG`, `.FILL`, `.BLKW`, `.STRINGZ`, `.EXTERNAL`, `.GLOBL`
+/// and `.END`.
+///
+/// `RET`, `NOP` and the trap aliases `GETC`, `OUT`, `PUTS`, `IN`, `PUTSP` and `HALT` are
+/// accepted as instructions without operands.
+///
+/// Labels named by `.EXTERNAL` are left to [`link`], they can be used by `.FILL` and by
+/// the instructions taking a PC offset. They resolve to the labels other modules name with
+/// `.GLOBL` (or `.GLOBAL`), the other labels stay local to their module.
+pub fn assemble(source: &str) -> Result<Assembled, AsmError> {
+    let statements: Vec<Statement> = source
+        .lines()
+        .enumerate()
+        .map(|(i, text)| parse_statement(i + 1, text))
+        .take_while(|s| s.mnemonic.as_deref() != Some(".END"))
+        .filter(|s| s.label.is_some() || s.mnemonic.is_some())
+        .collect();
+
+    let (first, statements) = statements.split_first().ok_or(AsmError::MissingOrigin)?;
+    if first.mnemonic.as_deref() != Some(".ORIG") {
+        return Err(AsmError::MissingOrigin);
+    }
+    expect_operands(first.line, &first.operands, 1)?;
+    let origin = parse_number(&first.operands[0]).ok_or_else(|| AsmError::InvalidOperand {
+        line: first.line,
+        operand: first.operands[0].clone(),
+    })? as u16;
+
+    // First pass: place the labels
+    let mut symbols = HashMap::new();
+    let mut externals = HashSet::new();
+    let mut exports = Vec::new();
+    let mut address = origin as u32;
+    for statement in statements {
+        match statement.mnemonic.as_deref() {
+            Some(".EXTERNAL") => externals.extend(statement.operands.iter().cloned()),
+            Some(".GLOBL") | Some(".GLOBAL") => exports.extend(
+                statement
+                    .operands
+                    .iter()
+                    .map(|label| (statement.line, label.clone())),
+            ),
+            _ => {}
+        }
+        if let Some(label) = &statement.label {
+            if symbols.insert(label.clone(), address as u16).is_some() {
+                return Err(AsmError::DuplicateLabel {
+                    line: statement.line,
+                    label: label.clone(),
+                });
+            }
+        }
+        address += size(statement)?;
+        if address > 0x10000 {
+            return Err(AsmError::ImageTooLarge {
+                line: statement.line,
+            });
+        }
+    }
+    if let Some(label) = externals.iter().find(|label| symbols.contains_key(*label)) {
+        return Err(AsmError::DuplicateLabel {
+            line: statements
+                .iter()
+                .find(|s| s.label.as_ref() == Some(label))
+                .map_or(0, |s| s.line),
+            label: label.clone(),
+        });
+    }
+    if let Some((line, label)) = exports
+        .iter()
+        .find(|(_, label)| !symbols.contains_key(label))
+    {
+        return Err(AsmError::UndefinedLabel {
+            line: *line,
+            label: label.clone(),
+        });
+    }
+
+    // Second pass: encode
+    let mut assembled = Assembled {
+        origin,
+        words: Vec::new(),
+        symbols,
+        data: HashSet::new(),
+        relocations: Vec::new(),
+        lines: HashMap::new(),
+        exports: exports.into_iter().map(|(_, label)| label).collect(),
+    };
+    for statement in statements {
+        let Some(mnemonic) = statement.mnemonic.as_deref() else {
+            continue;
+        };
+        if matches!(mnemonic, ".EXTERNAL" | ".GLOBL" | ".GLOBAL") {
+            continue;
+        }
+        let pc = origin.wrapping_add(assembled.words.len() as u16);
+
+        // An external label encodes as a zero offset (or address) for the linker to patch
+        let external = statement.operands.iter().find(|o| externals.contains(*o));
+        if let Some(label) = external {
+            let offset_bits = match mnemonic {
+                ".FILL" => None,
+                "JSR" => Some(11),
+                "LD" | "LDI" | "LEA" | "ST" | "STI" => Some(9),
+                br if br.starts_with("BR") => Some(9),
+                _ => {
+                    return Err(AsmError::InvalidOperand {
+                        line: statement.line,
+                        operand: label.clone(),
+                    })
+                }
+            };
+            let placeholder = match offset_bits {
+                Some(_) => pc.wrapping_add(1),
+                None => 0,
+            };
+            assembled.symbols.insert(label.clone(), placeholder);
+            assembled.relocations.push(Relocation {
+                address: pc,
+                label: label.clone(),
+                offset_bits,
+            });
+        }
+
+        let ctx = Context {
+            line: statement.line,
+            pc,
+            symbols: &assembled.symbols,
+        };
+        let operands = &statement.operands;
+
+        let data = match mnemonic {
+            ".FILL" => {
+                expect_operands(ctx.line, operands, 1)?;
+                let value = match parse_number(&operands[0]) {
+                    Some(n) => n as u16,
+                    None => ctx.label(&operands[0])?,
+                };
+                vec![value]
+            }
+            ".BLKW" => vec![0; size(statement)? as usize],
+            ".STRINGZ" => {
+                let mut words = parse_string(ctx.line, &operands[0])?;
+                words.push(0);
+                words
+            }
+            ".ORIG" => {
+                return Err(AsmError::UnknownMnemonic {
+                    line: ctx.line,
+                    mnemonic: mnemonic.to_string(),
+                })
+            }
+            _ => {
+                let word = encode(mnemonic, operands, &ctx)?;
+                assembled.lines.insert(pc, ctx.line);
+                assembled.words.push(word);
+                continue;
+            }
+        };
+        for (i, word) in data.into_iter().enumerate() {
+            assembled.data.insert(pc.wrapping_add(i as u16));
+            assembled.words.push(word);
+        }
+    }
+    for label in &externals {
+        assembled.symbols.remove(label);
+    }
+    Ok(assembled)
+}
+
+/// Place the modules at their origin in a single image, resolving their `.EXTERNAL` labels
+/// with the labels the others export with `.GLOBL`. Other labels are local, several modules
+/// can each have their own `LOOP`.
+///
+/// The symbols of the linked program are the exported labels and the local labels defined by
+/// a single module.
+pub fn link(modules: &[Assembled]) -> Result<Assembled, LinkError> {
+    let mut exported = HashMap::new();
+    for module in modules {
+        for label in &module.exports {
+            if exported
+                .insert(label.clone(), module.symbols[label])
+                .is_some()
+            {
+                return Err(LinkError::DuplicateSymbol {
+                    label: label.clone(),
+                });
+            }
+        }
+    }
+
+    let origin = modules.iter().map(|m| m.origin).min().unwrap_or_default();
+    let end = modules
+        .iter()
+        .map(|m| m.origin as usize + m.words.len())
+        .max()
+        .unwrap_or(origin as usize);
+    let mut words = vec![0; end - origin as usize];
+    let mut placed = vec![false; words.len()];
+    let mut data = HashSet::new();
+    let mut lines = HashMap::new();
+
+    for module in modules {
+        for (i, word) in module.words.iter().enumerate() {
+            let index = module.origin as usize - origin as usize + i;
+            if placed[index] {
+                return Err(LinkError::Overlap {
+                    address: (origin as usize + index) as u16,
+                });
+            }
+            placed[index] = true;
+            words[index] = *word;
+        }
+        data.extend(&module.data);
+        lines.extend(&module.lines);
+
+        for relocation in &module.relocations {
+            let target =
+                *exported
+                    .get(&relocation.label)
+                    .ok_or_else(|| LinkError::UnresolvedExternal {
+                        label: relocation.label.clone(),
+                    })?;
+            let index = (relocation.address - origin) as usize;
+            match relocation.offset_bits {
+                None => words[index] = target,
+                Some(bits) => {
+                    words[index] |= pcrel(relocation.address, target, bits).map_err(|_| {
+                        LinkError::OutOfRange {
+                            address: relocation.address,
+                            label: relocation.label.clone(),
+                        }
+                    })?;
+                }
+            }
+        }
+    }
+
+    let mut definitions: HashMap<&String, usize> = HashMap::new();
+    for label in modules.iter().flat_map(|m| m.symbols.keys()) {
+        *definitions.entry(label).or_default() += 1;
+    }
+    let mut symbols = exported;
+    for module in modules {
+        for (label, address) in &module.symbols {
+            if definitions[label] == 1 {
+                symbols.insert(label.clone(), *address);
+            }
+        }
+    }
+
+    Ok(Assembled {
+        origin,
+        words,
+        symbols,
+        data,
+        relocations: Vec::new(),
+        lines,
+        exports: modules.iter().flat_map(|m| m.exports.clone()).collect(),
+    })
+}
+
+/// An instruction with its fields, for building programs without source nor binary literals.
+/// Registers are numbers 0-7 and offsets are relative to the incremented PC (or to the base
+/// register), they are truncated to the width of their field
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Add {
+        dr: u16,
+        sr1: u16,
+        sr2: u16,
+    },
+    AddImm {
+        dr: u16,
+        sr1: u16,
+        imm: i16,
+    },
+    And {
+        dr: u16,
+        sr1: u16,
+        sr2: u16,
+    },
+    AndImm {
+        dr: u16,
+        sr1: u16,
+        imm: i16,
+    },
+    Br {
+        n: bool,
+        z: bool,
+        p: bool,
+        offset: i16,
+    },
+    Jmp {
+        base: u16,
+    },
+    Ret,
+    Jsr {
+        offset: i16,
+    },
+    Jsrr {
+        base: u16,
+    },
+    Ld {
+        dr: u16,
+        offset: i16,
+    },
+    Ldi {
+        dr: u16,
+        offset: i16,
+    },
+    Ldr {
+        dr: u16,
+        base: u16,
+        offset: i16,
+    },
+    Lea {
+        dr: u16,
+        offset: i16,
+    },
+    Not {
+        dr: u16,
+        sr: u16,
+    },
+    Rti,
+    St {
+        sr: u16,
+        offset: i16,
+    },
+    Sti {
+        sr: u16,
+        offset: i16,
+    },
+    Str {
+        sr: u16,
+        base: u16,
+        offset: i16,
+    },
+    Trap(u8),
+    /// A data word
+    Fill(u16),
+}
+
+impl Op {
+    pub fn encode(&self) -> u16 {
+        let field = |value: i16, bits: u32| value as u16 & ((1 << bits) - 1);
+        match *self {
+            Op::Add { dr, sr1, sr2 } => 0b0001 << 12 | dr << 9 | sr1 << 6 | sr2,
+            Op::AddImm { dr, sr1, imm } => {
+                0b0001 << 12 | dr << 9 | sr1 << 6 | 1 << 5 | field(imm, 5)
+            }
+            Op::And { dr, sr1, sr2 } => 0b0101 << 12 | dr << 9 | sr1 << 6 | sr2,
+            Op::AndImm { dr, sr1, imm } => {
+                0b0101 << 12 | dr << 9 | sr1 << 6 | 1 << 5 | field(imm, 5)
+            }
+            Op::Br { n, z, p, offset } => {
+                (n as u16) << 11 | (z as u16) << 10 | (p as u16) << 9 | field(offset, 9)
+            }
+            Op::Jmp { base } => 0b1100 << 12 | base << 6,
+            Op::Ret => 0b1100 << 12 | 7 << 6,
+            Op::Jsr { offset } => 0b0100 << 12 | 1 << 11 | field(offset, 11),
+            Op::Jsrr { base } => 0b0100 << 12 | base << 6,
+            Op::Ld { dr, offset } => 0b0010 << 12 | dr << 9 | field(offset, 9),
+            Op::Ldi { dr, offset } => 0b1010 << 12 | dr << 9 | field(offset, 9),
+            Op::Ldr { dr, base, offset } => 0b0110 << 12 | dr << 9 | base << 6 | field(offset, 6),
+            Op::Lea { dr, offset } => 0b1110 << 12 | dr << 9 | field(offset, 9),
+            Op::Not { dr, sr } => 0b1001 << 12 | dr << 9 | sr << 6 | 0b111111,
+            Op::Rti => 0b1000 << 12,
+            Op::St { sr, offset } => 0b0011 << 12 | sr << 9 | field(offset, 9),
+            Op::Sti { sr, offset } => 0b1011 << 12 | sr << 9 | field(offset, 9),
+            Op::Str { sr, base, offset } => 0b0111 << 12 | sr << 9 | base << 6 | field(offset, 6),
+            Op::Trap(vector) => 0b1111 << 12 | vector as u16,
+            Op::Fill(word) => word,
+        }
+    }
+}
+
+/// Words of a program made of `ops`, to be placed at an origin e.g. with [`crate::words_to_obj`]
+pub fn encode_program(ops: &[Op]) -> Vec<u16> {
+    ops.iter().map(Op::encode).collect()
+}
+
+/// Encode a single instruction (or `.FILL`) at `pc`, the inverse of [`disassemble`].
+/// Offsets are numeric, labels are rejected as undefined.
+pub fn assemble_line(text: &str, pc: u16) -> Result<u16, AsmError> {
+    let statement = parse_statement(1, text);
+    if let Some(label) = statement.label {
+        return Err(AsmError::UnknownMnemonic {
+            line: 1,
+            mnemonic: label,
+        });
+    }
+    let no_symbols = HashMap::new();
+    let ctx = Context {
+        line: 1,
+        pc,
+        symbols: &no_symbols,
+    };
+    let mnemonic = statement.mnemonic.unwrap_or_default();
+    if mnemonic == ".FILL" {
+        expect_operands(1, &statement.operands, 1)?;
+        Ok(ctx.number(&statement.operands[0])? as u16)
+    } else {
+        encode(&mnemonic, &statement.operands, &ctx)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RoundtripError {
+    Asm(AsmError),
+    /// The disassembly of the instruction at `address` assembles to another word
+    Mismatch {
+        address: u16,
+        original: u16,
+        reassembled: u16,
+    },
+}
+
+impl fmt::Display for RoundtripError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RoundtripError::Asm(e) => write!(f, "{e}"),
+            RoundtripError::Mismatch {
+                address,
+                original,
+                reassembled,
+            } => write!(
+                f,
+                "x{address:04X}: x{original:04X} disassembles to something assembling to x{reassembled:04X}"
+            ),
+        }
+    }
+}
+
+impl Error for RoundtripError {}
+
+impl From<AsmError> for RoundtripError {
+    fn from(e: AsmError) -> Self {
+        RoundtripError::Asm(e)
+    }
+}
+
+/// Assemble `source`, disassemble every instruction and check it assembles back to the same word
+pub fn validate_roundtrip(source: &str) -> Result<(), RoundtripError> {
+    let assembled = assemble(source)?;
+
+    for (i, &original) in assembled.words.iter().enumerate() {
+        let address = assembled.origin.wrapping_add(i as u16);
+        if assembled.data.contains(&address) {
+            continue;
+        }
+
+        let reassembled = assemble_line(&disassemble(original), address)?;
+
+        if reassembled != original {
+            return Err(RoundtripError::Mismatch {
+                address,
+                original,
+                reassembled,
+            });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+#[allow(clippy::unusual_byte_groupings)]
+mod tests {
+
+    use super::*;
+
+    const PROGRAM: &str = r#"
+; exercises every opcode
+        .ORIG x3000
+START   LEA R0, MSG         ; string address
+        LD R1, COUNT
+        LDI R2, PTR
+        LDR R3, R0, #1
+        ADD R1, R1, #-1
+        ADD R4, R1, R2
+        AND R5, R4, #15
+        AND R6, R4, R5
+        NOT R7, R6
+        ST R1, COUNT
+        STI R2, PTR
+        STR R3, R0, #2
+        BRnp SKIP
+        JSR SUB
+SKIP    JSRR R5
+        JMP R7
+        TRAP x25
+SUB     RTI
+COUNT   .FILL #10
+PTR     .FILL COUNT
+BUF     .BLKW 2
+MSG     .STRINGZ "Hi; \"you\"\n"
+        .END
+        garbage after the end
+"#;
+
+    #[test]
+    fn test_assemble() {
+        let assembled = assemble(PROGRAM).unwrap();
+
+        assert_eq!(assembled.origin, 0x3000);
+        assert_eq!(assembled.symbols["SUB"], 0x3011);
+        assert_eq!(assembled.symbols["MSG"], 0x3016);
+        assert_eq!(assembled.words[0], 0b1110_000_000010101); // LEA R0, MSG
+        assert_eq!(assembled.words[1], 0b0010_001_000010000); // LD R1, COUNT
+        assert_eq!(assembled.words[4], 0b0001_001_001_1_11111); // ADD R1, R1, #-1
+        assert_eq!(assembled.words[8], 0b1001_111_110_111111); // NOT R7, R6
+        assert_eq!(assembled.words[12], 0b0000_101_000000001); // BRnp SKIP
+        assert_eq!(assembled.words[13], 0b0100_1_00000000011); // JSR SUB
+        assert_eq!(assembled.words[16], 0xF025);
+        assert_eq!(assembled.words[19], 0x3012); // PTR .FILL COUNT
+        assert_eq!(assembled.words[20..22], [0, 0]);
+        assert_eq!(
+            assembled.words[22..],
+            "Hi; \"you\"\n\0"
+                .chars()
+                .map(|c| c as u16)
+                .collect::<Vec<_>>()
+        );
+        assert!(assembled.data.contains(&0x3012));
+        assert!(!assembled.data.contains(&0x3011));
+        assert_eq!(assembled.lines[&0x3000], 4);
+        assert_eq!(assembled.lines[&0x3011], 21); // SUB RTI
+        assert!(!assembled.lines.contains_key(&0x3012));
+    }
+
+    #[test]
+    fn test_assemble_errors() {
+        assert_eq!(assemble("ADD R0, R0, R0"), Err(AsmError::MissingOrigin));
+        assert_eq!(
+            assemble(".ORIG x3000\nFOO R1\n"),
+            Err(AsmError::UnknownMnemonic {
+                line: 2,
+                mnemonic: String::from("R1")
+            })
+        );
+        assert_eq!(
+            assemble(".ORIG x3000\nADD R0, R8, #1"),
+            Err(AsmError::InvalidOperand {
+                line: 2,
+                operand: String::from("R8")
+            })
+        );
+        assert_eq!(
+            assemble(".ORIG x3000\nBR NOWHERE"),
+            Err(AsmError::UndefinedLabel {
+                line: 2,
+                label: String::from("NOWHERE")
+            })
+        );
+        assert_eq!(
+            assemble(".ORIG x3000\nA ADD R0, R0, #1\nA ADD R0, R0, #1"),
+            Err(AsmError::DuplicateLabel {
+                line: 3,
+                label: String::from("A")
+            })
+        );
+        assert_eq!(
+            assemble(".ORIG x3000\nNOT R0"),
+            Err(AsmError::OperandCount {
+                line: 2,
+                expected: 2,
+                found: 1
+            })
+        );
+        assert_eq!(
+            assemble(".ORIG x3000\nBR FAR\n.BLKW 256\nFAR TRAP x25"),
+            Err(AsmError::OutOfRange {
+                line: 2,
+                label: String::from("FAR")
+            })
+        );
+        assert_eq!(
+            assemble(".ORIG x3000\n.BLKW #-1"),
+            Err(AsmError::OperandOutOfRange {
+                line: 2,
+                field: ".BLKW count",
+                value: -1,
+                range: 0..=0xFFFF
+            })
+        );
+        assert_eq!(
+            assemble(".ORIG x3000\n.BLKW x10000"),
+            Err(AsmError::OperandOutOfRange {
+                line: 2,
+                field: ".BLKW count",
+                value: 0x10000,
+                range: 0..=0xFFFF
+            })
+        );
+        assert_eq!(
+            assemble(".ORIG x3000\nHALT\n.BLKW xFFFF\n.FILL 1"),
+            Err(AsmError::ImageTooLarge { line: 3 })
+        );
+        assert_eq!(
+            assemble(".ORIG xFFFE\nHALT\nHALT\nHALT"),
+            Err(AsmError::ImageTooLarge { line: 4 })
+        );
+        assert_eq!(
+            assemble(".ORIG xFFFE\nHALT\n.FILL 1").map(|a| a.words.len()),
+            Ok(2)
+        );
+    }
+
+    #[test]
+    fn test_encode_ops() {
+        let ops = [
+            Op::Add {
+                dr: 0,
+                sr1: 1,
+                sr2: 2,
+            },
+            Op::AndImm {
+                dr: 7,
+                sr1: 0,
+                imm: -1,
+            },
+            Op::Br {
+                n: true,
+                z: false,
+                p: true,
+                offset: -1,
+            },
+            Op::Ldr {
+                dr: 2,
+                base: 7,
+                offset: 1,
+            },
+            Op::Str {
+                sr: 4,
+                base: 5,
+                offset: -1,
+            },
+            Op::Not { dr: 0, sr: 1 },
+            Op::Ret,
+            Op::Jsrr { base: 3 },
+            Op::Sti { sr: 1, offset: 2 },
+            Op::Rti,
+        ];
+        let texts = [
+            "ADD R0, R1, R2",
+            "AND R7, R0, #-1",
+            "BRnp #-1",
+            "LDR R2, R7, #1",
+            "STR R4, R5, #-1",
+            "NOT R0, R1",
+            "JMP R7",
+            "JSRR R3",
+            "STI R1, #2",
+            "RTI",
+        ];
+        for (op, text) in ops.iter().zip(texts) {
+            assert_eq!(disassemble(op.encode()), text);
+        }
+    }
+
+    #[test]
+    fn test_operand_out_of_range() {
+        assert_eq!(
+            assemble(".ORIG x3000\nADD R0, R1, #16"),
+            Err(AsmError::OperandOutOfRange {
+                line: 2,
+                field: "imm5",
+                value: 16,
+                range: -16..=15
+            })
+        );
+        assert!(assemble(".ORIG x3000\nADD R0, R1, #-16").is_ok());
+        assert_eq!(
+            assemble(".ORIG x3000\nBRz #-257"),
+            Err(AsmError::OperandOutOfRange {
+                line: 2,
+                field: "PCoffset9",
+                value: -257,
+                range: -256..=255
+            })
+        );
+        assert!(assemble(".ORIG x3000\nBRz #255").is_ok());
+        assert!(matches!(
+            assemble(".ORIG x3000\nLDR R0, R1, #32"),
+            Err(AsmError::OperandOutOfRange {
+                field: "offset6",
+                ..
+            })
+        ));
+        assert!(matches!(
+            assemble(".ORIG x3000\nTRAP x100"),
+            Err(AsmError::OperandOutOfRange {
+                field: "trapvect8",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_pcrel() {
+        assert_eq!(pcrel9(0x3000, 0x3001), Ok(0));
+        assert_eq!(pcrel9(0x3000, 0x3100), Ok(0xFF));
+        assert_eq!(pcrel9(0x3000, 0x2F01), Ok(0x100));
+        assert_eq!(pcrel9(0x3000, 0x3000), Ok(0x1FF));
+        assert_eq!(
+            pcrel9(0x3000, 0x3101),
+            Err(RangeError {
+                from: 0x3000,
+                to: 0x3101,
+                bits: 9
+            })
+        );
+        assert!(pcrel9(0x3000, 0x2F00).is_err());
+
+        assert_eq!(pcrel11(0x3000, 0x3400), Ok(0x3FF));
+        assert_eq!(pcrel11(0x3000, 0x2C01), Ok(0x400));
+        assert!(pcrel11(0x3000, 0x3401).is_err());
+        assert!(pcrel11(0x3000, 0x2C00).is_err());
+
+        assert_eq!(pcrel6(0x3000, 0x3020), Ok(0x1F));
+        assert_eq!(pcrel6(0x3000, 0x2FE1), Ok(0x20));
+        assert!(pcrel6(0x3000, 0x3021).is_err());
+        assert!(pcrel6(0x3000, 0x2FE0).is_err());
+    }
+
+    #[test]
+    fn test_link() {
+        let main = assemble(
+            r#"
+        .ORIG x3000
+        .EXTERNAL PRINT, COUNT
+        LD R1, COUNT
+        JSR PRINT
+        HALT_IT TRAP x25
+PTR     .FILL PRINT
+        .END
+"#,
+        )
+        .unwrap();
+        let lib = assemble(
+            r#"
+        .ORIG x3010
+        .GLOBL PRINT, COUNT
+PRINT   ADD R0, R0, #1
+        JMP R7
+COUNT   .FILL #3
+        .END
+"#,
+        )
+        .unwrap();
+        assert_eq!(main.relocations.len(), 3);
+        assert!(!main.symbols.contains_key("PRINT"));
+        assert_eq!(
+            link(std::slice::from_ref(&main)),
+            Err(LinkError::UnresolvedExternal {
+                label: String::from("COUNT")
+            })
+        );
+
+        let linked = link(&[main, lib]).unwrap();
+        assert_eq!(linked.origin, 0x3000);
+        assert_eq!(linked.words.len(), 0x13);
+        assert_eq!(linked.words[0], 0b0010_001_000010001); // LD R1, COUNT
+        assert_eq!(linked.words[1], 0b0100_1_00000001110); // JSR PRINT
+        assert_eq!(linked.words[3], 0x3010); // PTR .FILL PRINT
+        assert_eq!(linked.words[0x12], 3);
+        assert_eq!(linked.symbols["HALT_IT"], 0x3002);
+        assert!(linked.data.contains(&0x3012));
+        assert!(linked.relocations.is_empty());
+        assert_eq!(linked.exports.len(), 2);
+
+        // Both modules have their own LOOP, only PRINT is shared
+        let main = assemble(
+            r#"
+        .ORIG x3000
+        .EXTERNAL PRINT
+LOOP    JSR PRINT
+        BRp LOOP
+        HALT
+        .END
+"#,
+        )
+        .unwrap();
+        let lib = assemble(
+            r#"
+        .ORIG x3010
+        .GLOBAL PRINT
+PRINT   ADD R0, R0, #-1
+LOOP    BRz LOOP
+        RET
+        .END
+"#,
+        )
+        .unwrap();
+        let linked = link(&[main, lib]).unwrap();
+        assert_eq!(linked.words[0], 0b0100_1_00000001111); // JSR PRINT
+        assert_eq!(linked.words[1], 0b0000_001_111111110); // BRp x3000
+        assert_eq!(linked.words[0x11], 0b0000_010_111111111); // BRz x3011
+        assert_eq!(linked.symbols["PRINT"], 0x3010);
+        assert!(!linked.symbols.contains_key("LOOP"));
+    }
+
+    #[test]
+    fn test_link_errors() {
+        let a = assemble(".ORIG x3000\nA ADD R0, R0, #1\nB ADD R0, R0, #1").unwrap();
+        let b = assemble(".ORIG x3001\nC ADD R0, R0, #1").unwrap();
+        assert_eq!(
+            link(&[a.clone(), b]),
+            Err(LinkError::Overlap { address: 0x3001 })
+        );
+
+        let exported = assemble(".ORIG x3000\n.GLOBL A\nA ADD R0, R0, #1").unwrap();
+        let dup = assemble(".ORIG x4000\n.GLOBL A\nA ADD R0, R0, #1").unwrap();
+        assert_eq!(
+            link(&[exported, dup]),
+            Err(LinkError::DuplicateSymbol {
+                label: String::from("A")
+            })
+        );
+
+        let far = assemble(".ORIG x3000\n.EXTERNAL FAR\nBR FAR").unwrap();
+        let target = assemble(".ORIG x4000\n.GLOBL FAR\nFAR ADD R0, R0, #1").unwrap();
+        let local = assemble(".ORIG x4000\nFAR ADD R0, R0, #1").unwrap();
+        assert_eq!(
+            link(&[far.clone(), local]),
+            Err(LinkError::UnresolvedExternal {
+                label: String::from("FAR")
+            })
+        );
+        assert_eq!(
+            assemble(".ORIG x3000\n.GLOBL NOWHERE\nHALT"),
+            Err(AsmError::UndefinedLabel {
+                line: 2,
+                label: String::from("NOWHERE")
+            })
+        );
+        assert_eq!(
+            link(&[far, target]),
+            Err(LinkError::OutOfRange {
+                address: 0x3000,
+                label: String::from("FAR")
+            })
+        );
+    }
+
+    #[test]
+    fn test_assemble_line() {
+        let word = assemble_line("ADD R0, R1, #3", 0x3000).unwrap();
+        assert_eq!(word, 0b0001_000_001_1_00011);
+        assert_eq!(disassemble(word), "ADD R0, R1, #3");
+        assert_eq!(assemble_line(&disassemble(word), 0x3000), Ok(word));
+
+        assert_eq!(assemble_line("BRz #-2", 0x3000), Ok(0b0000_010_111111110));
+        assert_eq!(assemble_line(".FILL x1234", 0x3000), Ok(0x1234));
+        assert_eq!(
+            assemble_line("BR LOOP", 0x3000),
+            Err(AsmError::UndefinedLabel {
+                line: 1,
+                label: String::from("LOOP")
+            })
+        );
+        assert_eq!(
+            assemble_line("LOOP ADD R0, R0, #1", 0x3000),
+            Err(AsmError::UnknownMnemonic {
+                line: 1,
+                mnemonic: String::from("LOOP")
+            })
+        );
+    }
+
+    #[test]
+    fn test_pseudo_ops() {
+        for (source, word) in [
+            ("RET", 0xC1C0),
+            ("NOP", 0x0000),
+            ("GETC", 0xF020),
+            ("OUT", 0xF021),
+            ("PUTS", 0xF022),
+            ("IN", 0xF023),
+            ("PUTSP", 0xF024),
+            ("HALT", 0xF025),
+            ("halt", 0xF025),
+        ] {
+            assert_eq!(assemble_line(source, 0x3000), Ok(word), "{source}");
+        }
+        assert_eq!(
+            assemble_line("RET", 0x3000),
+            assemble_line("JMP R7", 0x3000)
+        );
+        assert_eq!(
+            assemble_line("HALT", 0x3000),
+            assemble_line("TRAP x25", 0x3000)
+        );
+        assert_eq!(
+            assemble_line("HALT x25", 0x3000),
+            Err(AsmError::OperandCount {
+                line: 1,
+                expected: 0,
+                found: 1
+            })
+        );
+
+        let assembled = assemble(".ORIG x3000\nLOOP HALT\n.END").unwrap();
+        assert_eq!(assembled.words, vec![0xF025]);
+        assert_eq!(assembled.symbols["LOOP"], 0x3000);
+    }
+
+    #[test]
+    fn test_validate_roundtrip() {
+        assert_eq!(validate_roundtrip(PROGRAM), Ok(()));
+        assert!(matches!(
+            validate_roundtrip("ADD R0, R0, R0"),
+            Err(RoundtripError::Asm(AsmError::MissingOrigin))
+        ));
+    }
+}
@@ -0,0 +1,100 @@
+use std::ops::Range;
+
+/// Options changing how the VM behaves, the defaults match the plain LC-3
+#[derive(Debug, Clone, Default)]
+pub struct VmConfig {
+    /// Echo the character read by the GETC trap, handy when debugging with input buffering off.
+    /// IN always echoes, GETC never does unless this is set.
+    pub echo_getc: bool,
+    /// Only accept the standard LC-3, extensions like the debug traps are rejected
+    pub strict: bool,
+    /// Memory region used as a display, one word per pixel/character cell, see [`crate::VM::framebuffer`]
+    pub framebuffer: Option<Range<u16>>,
+    /// What the timer interval (TMI) counts, see [`TimerSource`]
+    pub timer_source: TimerSource,
+    /// Stop with [`crate::RunResult::ProtectionViolation`] on any store to x0000-x00FF (the trap vector table)
+    pub protect_zero_page: bool,
+    /// What GETC and IN do once the input is exhausted, see [`EofBehavior`]
+    pub eof_behavior: EofBehavior,
+    /// Count how many times each address is executed, see [`crate::VM::hot_addresses`]
+    pub profile_addresses: bool,
+    /// Stop with [`crate::RunResult::DataExecution`] before executing an address marked as data
+    /// by [`crate::VM::mark_data`]
+    pub strict_execution: bool,
+    /// Behave like the C VM of the "Write your own virtual machine" tutorial, that many LC-3
+    /// binaries were tested against:
+    /// - GETC and IN set the condition codes from R0
+    /// - IN prints the `Enter a character: ` prompt before reading
+    /// - HALT sets R7 like the other traps and prints `HALT`
+    /// - a trap vector without native routine only sets R7 instead of going through the table
+    pub compat_reference: bool,
+    /// What a read of a device address (xFE00 and above) with no device behind it does
+    pub bus_mode: BusMode,
+    /// How a TRAP hands back control, see [`TrapReturn`]
+    pub trap_return: TrapReturn,
+    /// Most bytes the traps may output, to contain a guest printing forever
+    pub max_output_bytes: Option<usize>,
+    /// What happens to output past [`VmConfig::max_output_bytes`]
+    pub output_overflow: OutputOverflow,
+    /// Benchmark mode: the traps output nothing and GETC and IN read the given byte without
+    /// touching the reader, so a run measures the interpreter rather than the terminal
+    pub null_io: Option<u8>,
+    /// Have [`crate::VM::debug_step`] stop on the first instruction of a trap routine installed
+    /// in the trap vector table rather than running the whole routine
+    pub step_into_traps: bool,
+}
+
+/// Clock driving the timer device
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TimerSource {
+    /// The interval is a number of executed instructions, runs are reproducible
+    #[default]
+    Instructions,
+    /// The interval is a number of milliseconds, for interactive programs
+    WallClock,
+}
+
+/// Return convention of the traps, applied to both the native traps and the routines
+/// installed in the trap vector table
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TrapReturn {
+    /// LC-3 2nd edition: TRAP saves the return address in R7 and the routine ends with RET.
+    /// Native traps set R7 as such a routine would
+    #[default]
+    Ret,
+    /// LC-3 3rd edition: TRAP pushes the PSR then the return address on the R6 stack and the
+    /// routine ends with RTI, R7 is preserved. Native traps leave R7 and R6 untouched
+    Rti,
+}
+
+/// Handling of reads of the device page without a device, see [`VmConfig::bus_mode`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BusMode {
+    /// The read returns the memory cell, like any other address
+    #[default]
+    Flat,
+    /// The read stops the VM with [`crate::VmError::BusError`]
+    Strict,
+}
+
+/// Result of reading a character when the input has no more data
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EofBehavior {
+    /// R0 is set to x0000
+    #[default]
+    ReturnZero,
+    /// R0 is set to xFFFF, a sentinel no character can take
+    ReturnNegativeOne,
+    /// The VM halts as if the program ran HALT, R0 is left untouched
+    Halt,
+}
+
+/// Handling of the output past [`VmConfig::max_output_bytes`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputOverflow {
+    /// The extra bytes are dropped and the program keeps running
+    #[default]
+    Truncate,
+    /// The extra bytes are dropped and the VM stops with [`crate::RunResult::OutputLimitReached`]
+    Halt,
+}
@@ -0,0 +1,103 @@
+//! Execution counts of assembler source lines, for seeing which lines of a program actually ran.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{Read, Write};
+
+use crate::VM;
+
+/// How many times each instruction line of the assembled source ran, see [`VM::coverage_report`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CoverageReport {
+    /// Hit count by source line number, 0 for the lines that never ran
+    pub lines: BTreeMap<usize, u64>,
+}
+
+impl<R, W> VM<R, W>
+where
+    R: Read,
+    W: Write,
+{
+    /// Map the execution counts of [`VmConfig::profile_addresses`](crate::VmConfig) back to the
+    /// source lines of the programs loaded with [`VM::load_assembled`]. Without profiling every
+    /// line is reported as uncovered
+    pub fn coverage_report(&self) -> CoverageReport {
+        let mut lines = BTreeMap::new();
+        for (address, line) in &self.source_lines {
+            let count = self.address_counts.get(address).copied().unwrap_or(0);
+            *lines.entry(*line).or_default() += count;
+        }
+        CoverageReport { lines }
+    }
+}
+
+impl CoverageReport {
+    /// Source lines that never ran, in order
+    pub fn uncovered(&self) -> Vec<usize> {
+        self.lines
+            .iter()
+            .filter(|(_, count)| **count == 0)
+            .map(|(line, _)| *line)
+            .collect()
+    }
+
+    /// The report as an LCOV tracefile record for `source_file`: one `DA:<line>,<count>` per
+    /// line followed by the `LF` and `LH` totals
+    pub fn to_lcov(&self, source_file: &str) -> String {
+        let mut out = format!("SF:{source_file}\n");
+        for (line, count) in &self.lines {
+            let _ = writeln!(out, "DA:{line},{count}");
+        }
+        let hit = self.lines.values().filter(|count| **count > 0).count();
+        let _ = writeln!(out, "LF:{}", self.lines.len());
+        let _ = writeln!(out, "LH:{hit}");
+        out.push_str("end_of_record\n");
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::assembler::assemble;
+    use crate::VmConfig;
+
+    const DEAD_BRANCH: &str = r#"
+        .ORIG x3000
+        AND R0, R0, #0
+        ADD R0, R0, #1
+        BRp DONE
+        ADD R0, R0, #5  ; never runs
+DONE    HALT
+        .END
+"#;
+
+    #[test]
+    fn test_coverage_report() {
+        let assembled = assemble(DEAD_BRANCH).unwrap();
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.load_assembled(&assembled).unwrap();
+        vm.set_config(VmConfig {
+            profile_addresses: true,
+            ..VmConfig::default()
+        });
+        vm.run();
+
+        let report = vm.coverage_report();
+        assert_eq!(
+            report.lines,
+            BTreeMap::from([(3, 1), (4, 1), (5, 1), (6, 0), (7, 1)])
+        );
+        assert_eq!(report.uncovered(), vec![6]);
+        assert_eq!(
+            report.to_lcov("dead.asm"),
+            "SF:dead.asm\nDA:3,1\nDA:4,1\nDA:5,1\nDA:6,0\nDA:7,1\nLF:5\nLH:4\nend_of_record\n"
+        );
+
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.load_assembled(&assembled).unwrap();
+        vm.run();
+        assert_eq!(vm.coverage_report().uncovered(), vec![3, 4, 5, 6, 7]);
+    }
+}
@@ -0,0 +1,270 @@
+use std::io::Read;
+
+use crate::instructions::{get_cond, get_nth_bit, imm5, off11, off6, off9, sext};
+use crate::Reg;
+
+/// Format a sign-extended field as a signed decimal, e.g. `#-1`
+fn signed(n: u16, b: usize) -> String {
+    format!("#{}", sext(n, b) as i16)
+}
+
+/// Disassemble one instruction, PC-relative offsets are shown as they are encoded.
+///
+/// Words that are not a valid instruction (the reserved opcode, a BR without condition)
+/// are shown as `.FILL` so the output can be assembled back.
+pub fn disassemble(instruction: u16) -> String {
+    let opcode = instruction >> 12;
+    match opcode {
+        0b0000 => {
+            let nzp = get_cond(instruction);
+            if nzp == 0 {
+                return format!(".FILL x{instruction:04X}");
+            }
+            let mut mnemonic = String::from("BR");
+            for (bit, flag) in [(0b100, 'n'), (0b010, 'z'), (0b001, 'p')] {
+                if nzp & bit != 0 {
+                    mnemonic.push(flag);
+                }
+            }
+            format!("{mnemonic} {}", signed(off9(instruction), 9))
+        }
+        0b0001 | 0b0101 => {
+            let mnemonic = if opcode == 0b0001 { "ADD" } else { "AND" };
+            let (dr, sr1) = (Reg::dr(instruction), Reg::sr1(instruction));
+            if get_nth_bit(instruction, 5) {
+                let imm = signed(imm5(instruction), 5);
+                format!("{mnemonic} {dr:?}, {sr1:?}, {imm}")
+            } else {
+                format!("{mnemonic} {dr:?}, {sr1:?}, {:?}", Reg::sr2(instruction))
+            }
+        }
+        0b0010 | 0b0011 | 0b1010 | 0b1011 | 0b1110 => {
+            let mnemonic = match opcode {
+                0b0010 => "LD",
+                0b0011 => "ST",
+                0b1010 => "LDI",
+                0b1011 => "STI",
+                _ => "LEA",
+            };
+            let offset = signed(off9(instruction), 9);
+            format!("{mnemonic} {:?}, {offset}", Reg::dr(instruction))
+        }
+        0b0100 => {
+            if get_nth_bit(instruction, 11) {
+                format!("JSR {}", signed(off11(instruction), 11))
+            } else {
+                format!("JSRR {:?}", Reg::sr1(instruction))
+            }
+        }
+        0b0110 | 0b0111 => {
+            let mnemonic = if opcode == 0b0110 { "LDR" } else { "STR" };
+            let (dr, base) = (Reg::dr(instruction), Reg::sr1(instruction));
+            let offset = signed(off6(instruction), 6);
+            format!("{mnemonic} {dr:?}, {base:?}, {offset}")
+        }
+        0b1000 => String::from("RTI"),
+        0b1001 => format!(
+            "NOT {:?}, {:?}",
+            Reg::dr(instruction),
+            Reg::sr1(instruction)
+        ),
+        0b1100 => format!("JMP {:?}", Reg::sr1(instruction)),
+        0b1111 => format!("TRAP x{:02X}", instruction & 0xFF),
+        _ => format!(".FILL x{instruction:04X}"),
+    }
+}
+
+/// Instruction a word is executed as, one per case of the decoder
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Variant {
+    Br,
+    AddReg,
+    AddConst,
+    Ld,
+    St,
+    Jsr,
+    Jsrr,
+    AndReg,
+    AndConst,
+    Ldr,
+    Str,
+    Rti,
+    Not,
+    Ldi,
+    Sti,
+    Jmp,
+    /// Opcode 1101, not an instruction
+    Reserved,
+    Lea,
+    TrapGetC,
+    TrapOutC,
+    TrapPuts,
+    TrapIn,
+    TrapPutsp,
+    TrapHalt,
+    TrapInu16,
+    TrapOutu16,
+    TrapOuti16,
+    TrapDumpRegisters,
+    TrapInstructionCount,
+    TrapIni16,
+    TrapFlush,
+    /// A trap without native routine, going through the trap vector table
+    TrapVector(u8),
+}
+
+/// Which instruction `instruction` decodes to, without executing it
+pub fn variant_of(instruction: u16) -> Variant {
+    match instruction >> 12 {
+        0b0000 => Variant::Br,
+        0b0001 if get_nth_bit(instruction, 5) => Variant::AddConst,
+        0b0001 => Variant::AddReg,
+        0b0010 => Variant::Ld,
+        0b0011 => Variant::St,
+        0b0100 if get_nth_bit(instruction, 11) => Variant::Jsr,
+        0b0100 => Variant::Jsrr,
+        0b0101 if get_nth_bit(instruction, 5) => Variant::AndConst,
+        0b0101 => Variant::AndReg,
+        0b0110 => Variant::Ldr,
+        0b0111 => Variant::Str,
+        0b1000 => Variant::Rti,
+        0b1001 => Variant::Not,
+        0b1010 => Variant::Ldi,
+        0b1011 => Variant::Sti,
+        0b1100 => Variant::Jmp,
+        0b1101 => Variant::Reserved,
+        0b1110 => Variant::Lea,
+        _ => match instruction as u8 {
+            0x20 => Variant::TrapGetC,
+            0x21 => Variant::TrapOutC,
+            0x22 => Variant::TrapPuts,
+            0x23 => Variant::TrapIn,
+            0x24 => Variant::TrapPutsp,
+            0x25 => Variant::TrapHalt,
+            0x26 => Variant::TrapInu16,
+            0x27 => Variant::TrapOutu16,
+            0x28 => Variant::TrapOuti16,
+            0x2C => Variant::TrapDumpRegisters,
+            0x2D => Variant::TrapInstructionCount,
+            0x2E => Variant::TrapIni16,
+            0x2F => Variant::TrapFlush,
+            vect => Variant::TrapVector(vect),
+        },
+    }
+}
+
+/// Address reached by the PC-relative offset of the instruction at `address`, if it has one
+pub fn target(address: u16, instruction: u16) -> Option<u16> {
+    let offset = match instruction >> 12 {
+        0b0000 if get_cond(instruction) != 0 => sext(off9(instruction), 9),
+        0b0010 | 0b0011 | 0b1010 | 0b1011 | 0b1110 => sext(off9(instruction), 9),
+        0b0100 if get_nth_bit(instruction, 11) => sext(off11(instruction), 11),
+        _ => return None,
+    };
+    Some(address.wrapping_add(1).wrapping_add(offset))
+}
+
+/// Disassemble an object file without loading it, yielding `(address, word, mnemonic)`.
+///
+/// The first word of the program is its origin. A trailing odd byte is ignored.
+pub fn disassemble_stream<P>(mut program: P) -> impl Iterator<Item = (u16, u16, String)>
+where
+    P: Read,
+{
+    let mut buf = [0; 2];
+    let mut address = match program.read_exact(&mut buf) {
+        Ok(()) => Some(buf[1] as u16 | (buf[0] as u16) << 8),
+        Err(_) => None,
+    };
+
+    std::iter::from_fn(move || {
+        let current = address?;
+        if program.read_exact(&mut buf).is_err() {
+            address = None;
+            return None;
+        }
+        let word = buf[1] as u16 | (buf[0] as u16) << 8;
+        address = Some(current.wrapping_add(1));
+        Some((current, word, disassemble(word)))
+    })
+}
+
+#[cfg(test)]
+#[allow(clippy::unusual_byte_groupings)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_disassemble() {
+        assert_eq!(disassemble(0b0001_000_001_0_00_010), "ADD R0, R1, R2");
+        assert_eq!(disassemble(0b0001_000_011_1_11001), "ADD R0, R3, #-7");
+        assert_eq!(disassemble(0b0101_111_000_0_00_010), "AND R7, R0, R2");
+        assert_eq!(disassemble(0b0000_101_111111111), "BRnp #-1");
+        assert_eq!(disassemble(0b0000_111_000000011), "BRnzp #3");
+        assert_eq!(disassemble(0b0010_110_111111111), "LD R6, #-1");
+        assert_eq!(disassemble(0b1110_011_000010000), "LEA R3, #16");
+        assert_eq!(disassemble(0b0110_010_111_000001), "LDR R2, R7, #1");
+        assert_eq!(disassemble(0b0111_100_101_111111), "STR R4, R5, #-1");
+        assert_eq!(disassemble(0b1001_000_001_111111), "NOT R0, R1");
+        assert_eq!(disassemble(0b1100_000_111_000000), "JMP R7");
+        assert_eq!(disassemble(0b0100_1_11111111111), "JSR #-1");
+        assert_eq!(disassemble(0b0100_0_00_011_000000), "JSRR R3");
+        assert_eq!(disassemble(0xF025), "TRAP x25");
+        assert_eq!(disassemble(0x0000), ".FILL x0000");
+        assert_eq!(disassemble(0xD123), ".FILL xD123");
+    }
+
+    #[test]
+    fn test_variant_of() {
+        assert_eq!(variant_of(0b0001_000_001_0_00_010), Variant::AddReg);
+        assert_eq!(variant_of(0b0001_000_011_1_11001), Variant::AddConst);
+        assert_eq!(variant_of(0b0101_111_000_0_00_010), Variant::AndReg);
+        assert_eq!(variant_of(0b0101_111_000_1_00000), Variant::AndConst);
+        assert_eq!(variant_of(0b0100_1_11111111111), Variant::Jsr);
+        assert_eq!(variant_of(0b0100_0_00_011_000000), Variant::Jsrr);
+        assert_eq!(variant_of(0b1100_000_111_000000), Variant::Jmp);
+        assert_eq!(variant_of(0xD123), Variant::Reserved);
+        assert_eq!(variant_of(0xF020), Variant::TrapGetC);
+        assert_eq!(variant_of(0xF025), Variant::TrapHalt);
+        assert_eq!(variant_of(0xF02F), Variant::TrapFlush);
+        assert_eq!(variant_of(0xF030), Variant::TrapVector(0x30));
+    }
+
+    #[test]
+    fn test_target() {
+        assert_eq!(target(0x3000, 0b0000_101_111111111), Some(0x3000));
+        assert_eq!(target(0x3000, 0b1110_011_000010000), Some(0x3011));
+        assert_eq!(target(0x3000, 0b0100_1_00000000010), Some(0x3003));
+        assert_eq!(target(0x3000, 0b0100_0_00_011_000000), None);
+        assert_eq!(target(0x3000, 0b0001_000_011_1_11001), None);
+        assert_eq!(target(0x3000, 0x0000), None);
+    }
+
+    #[test]
+    fn test_disassemble_stream() {
+        let program: &[u8] = &[
+            0x30,
+            0x00, // origin
+            0b0001_0010,
+            0b0110_0011, // add r1/0 and 3 in r1
+            0b0010_1010,
+            0b0000_0011, // ld offset 3 in r5
+            0xF0,
+            0x25, // halt
+            0x42, // odd trailing byte
+        ];
+
+        let listing: Vec<(u16, u16, String)> = disassemble_stream(program).collect();
+
+        assert_eq!(
+            listing,
+            vec![
+                (0x3000, 0x1263, String::from("ADD R1, R1, #3")),
+                (0x3001, 0x2A03, String::from("LD R5, #3")),
+                (0x3002, 0xF025, String::from("TRAP x25")),
+            ]
+        );
+        assert_eq!(disassemble_stream(&[0x30][..]).count(), 0);
+    }
+}
@@ -1,15 +1,15 @@
 use std::fmt::Debug;
 use std::io::{Read, Write};
 
-use crate::{Reg, VM};
+use crate::{EofBehavior, Reg, RunResult, TrapReturn, VmError, MR_MCR, VM};
 
-fn imm5(instruction: u16) -> u16 {
+pub(crate) fn imm5(instruction: u16) -> u16 {
     instruction & 0b0000_0000_0001_1111
 }
 
 /// sext(n, b) Sign-extend n. The most significant bit of n is replicated as many times as necessary to
 // extend n to 16 bits. For example, if n = 110000, then SEXT(n, 6) = 1111 1111 1111 0000
-fn sext(n: u16, b: usize) -> u16 {
+pub(crate) fn sext(n: u16, b: usize) -> u16 {
     if (n >> (b - 1)) & 1 == 1 {
         n | (0xFFFF << b)
     } else {
@@ -18,29 +18,68 @@ fn sext(n: u16, b: usize) -> u16 {
 }
 
 /// get offset 9
-fn off9(n: u16) -> u16 {
+pub(crate) fn off9(n: u16) -> u16 {
     n & 0x1FF
 }
 
 /// get offset 6
-fn off6(n: u16) -> u16 {
+pub(crate) fn off6(n: u16) -> u16 {
     n & 0x3F
 }
 
 /// get offset 11
-fn off11(n: u16) -> u16 {
+pub(crate) fn off11(n: u16) -> u16 {
     n & 0x7FF
 }
 
 /// Extract the bits b11, b10, b9
-fn get_cond(instruction: u16) -> u16 {
+pub(crate) fn get_cond(instruction: u16) -> u16 {
     (instruction >> 9) & 0x07
 }
 
-fn get_nth_bit(instruction: u16, n: usize) -> bool {
+pub(crate) fn get_nth_bit(instruction: u16, n: usize) -> bool {
     ((instruction >> n) & 1) == 1
 }
 
+/// Read one character from the reader, 0 if nothing is available. `None` when the reader
+/// failed, the VM then stops with [`VmError::Io`]
+fn read_char<R: Read, W: Write>(vm: &mut VM<R, W>) -> Option<u8> {
+    match vm.read_byte() {
+        Ok(byte) => Some(byte.unwrap_or(0)),
+        Err(e) => {
+            vm.fault = Some(RunResult::Error(VmError::Io(e.kind())));
+            None
+        }
+    }
+}
+
+/// Read the character for GETC and IN, applying [`crate::VmConfig::eof_behavior`] once the input is
+/// exhausted. `None` when the VM halted instead or the reader failed, see [`read_char`].
+fn read_input<R: Read, W: Write>(vm: &mut VM<R, W>) -> Option<u16> {
+    if let Some(byte) = vm.config.null_io {
+        return Some(byte as u16);
+    }
+    match vm.read_byte() {
+        Ok(Some(byte)) => return Some(byte as u16),
+        Ok(None) => {}
+        Err(e) => {
+            vm.fault = Some(RunResult::Error(VmError::Io(e.kind())));
+            return None;
+        }
+    }
+    match vm.config.eof_behavior {
+        EofBehavior::ReturnZero => Some(0),
+        EofBehavior::ReturnNegativeOne => Some(0xFFFF),
+        EofBehavior::Halt => {
+            vm.halt = true;
+            vm.memory.mem[MR_MCR as usize] &= !(1 << 15);
+            None
+        }
+    }
+}
+
+/// An executable instruction, run with the PC already incremented past it as [`VM::step`] does:
+/// the PC-relative offsets of BR, JSR, LD, LDI, LEA, ST and STI apply to the next address
 pub(crate) trait Instruction<R, W>: Debug
 where
     R: Read,
@@ -49,68 +88,165 @@ where
     fn execute(&self, vm: &mut VM<R, W>);
 }
 
-impl<R, W> From<u16> for Box<dyn Instruction<R, W>>
-where
-    R: Read,
-    W: Write,
-{
-    fn from(instruction: u16) -> Self {
+/// A decoded instruction, executed with a `match` rather than through a trait object
+#[derive(Debug)]
+pub(crate) enum Op {
+    Br(Br),
+    AddConst(AddConst),
+    AddReg(AddReg),
+    Ld(Ld),
+    St(St),
+    Jsr(Jsr),
+    Jsrr(Jsrr),
+    AndConst(AndConst),
+    AndReg(AndReg),
+    Ldr(Ldr),
+    Str(Str),
+    Rti(Rti),
+    Not(Not),
+    Ldi(Ldi),
+    Sti(Sti),
+    Jmp(Jmp),
+    Reserved(Reserved),
+    Lea(Lea),
+    TrapGetC(TrapGetC),
+    TrapOutC(TrapOutC),
+    TrapPuts(TrapPuts),
+    TrapIn(TrapIn),
+    TrapPutsp(TrapPutsp),
+    TrapHalt(TrapHalt),
+    TrapInu16(TrapInu16),
+    TrapOutu16(TrapOutu16),
+    TrapOuti16(TrapOuti16),
+    TrapDumpRegisters(TrapDumpRegisters),
+    TrapInstructionCount(TrapInstructionCount),
+    TrapIni16(TrapIni16),
+    TrapFlush(TrapFlush),
+    TrapVector(TrapVector),
+}
+
+impl Op {
+    pub(crate) fn decode(instruction: u16) -> Self {
         let opcode = instruction >> 12;
         match opcode {
-            0b0000 => Box::new(Br::from(instruction)),
+            0b0000 => Op::Br(Br::from(instruction)),
             0b0001 => {
                 if get_nth_bit(instruction, 5) {
-                    Box::new(AddConst::from(instruction))
+                    Op::AddConst(AddConst::from(instruction))
                 } else {
-                    Box::new(AddReg::from(instruction))
+                    Op::AddReg(AddReg::from(instruction))
                 }
             }
-            0b0010 => Box::new(Ld::from(instruction)),
-            0b0011 => Box::new(St::from(instruction)),
+            0b0010 => Op::Ld(Ld::from(instruction)),
+            0b0011 => Op::St(St::from(instruction)),
             0b0100 => {
                 if get_nth_bit(instruction, 11) {
-                    Box::new(Jsr::from(instruction))
+                    Op::Jsr(Jsr::from(instruction))
                 } else {
-                    Box::new(Jsrr::from(instruction))
+                    Op::Jsrr(Jsrr::from(instruction))
                 }
             }
             0b0101 => {
                 if get_nth_bit(instruction, 5) {
-                    Box::new(AndConst::from(instruction))
+                    Op::AndConst(AndConst::from(instruction))
                 } else {
-                    Box::new(AndReg::from(instruction))
+                    Op::AndReg(AndReg::from(instruction))
                 }
             }
-            0b0110 => Box::new(Ldr::from(instruction)),
-            0b0111 => Box::new(Str::from(instruction)),
-            // 0b1000 => Op::Rti,
-            0b1001 => Box::new(Not::from(instruction)),
-            0b1010 => Box::new(Ldi::from(instruction)),
-            0b1011 => Box::new(Sti::from(instruction)),
-            0b1100 => Box::new(Jmp::from(instruction)),
-            // 0b1101 => Op::Unused,
-            0b1110 => Box::new(Lea::from(instruction)),
+            0b0110 => Op::Ldr(Ldr::from(instruction)),
+            0b0111 => Op::Str(Str::from(instruction)),
+            0b1000 => Op::Rti(Rti),
+            0b1001 => Op::Not(Not::from(instruction)),
+            0b1010 => Op::Ldi(Ldi::from(instruction)),
+            0b1011 => Op::Sti(Sti::from(instruction)),
+            0b1100 => Op::Jmp(Jmp::from(instruction)),
+            0b1101 => Op::Reserved(Reserved { instruction }),
+            0b1110 => Op::Lea(Lea::from(instruction)),
             0b1111 => {
                 let trap_vect = instruction & 0b0000000011111111;
                 match trap_vect {
-                    0x20 => Box::new(TrapGetC),
-                    0x21 => Box::new(TrapOutC),
-                    0x22 => Box::new(TrapPuts),
-                    0x23 => Box::new(TrapIn),
-                    0x24 => Box::new(TrapPutsp),
-                    0x25 => Box::new(TrapHalt),
-                    0x26 => Box::new(TrapInu16),
-                    0x27 => Box::new(TrapOutu16),
-                    _ => panic!("Trap vect {trap_vect:016b} as no matching trap"),
+                    0x20 => Op::TrapGetC(TrapGetC),
+                    0x21 => Op::TrapOutC(TrapOutC),
+                    0x22 => Op::TrapPuts(TrapPuts),
+                    0x23 => Op::TrapIn(TrapIn),
+                    0x24 => Op::TrapPutsp(TrapPutsp),
+                    0x25 => Op::TrapHalt(TrapHalt),
+                    0x26 => Op::TrapInu16(TrapInu16),
+                    0x27 => Op::TrapOutu16(TrapOutu16),
+                    0x28 => Op::TrapOuti16(TrapOuti16),
+                    0x2C => Op::TrapDumpRegisters(TrapDumpRegisters),
+                    0x2D => Op::TrapInstructionCount(TrapInstructionCount),
+                    0x2E => Op::TrapIni16(TrapIni16),
+                    0x2F => Op::TrapFlush(TrapFlush),
+                    _ => Op::TrapVector(TrapVector {
+                        vect: trap_vect as u8,
+                    }),
                 }
             }
             _ => panic!("Op code {instruction:016b} as no matching opcode"),
         }
     }
+
+    /// Vector of the traps this VM adds to the standard LC-3
+    fn extension_vector(&self) -> Option<u8> {
+        match self {
+            Op::TrapInu16(_) => Some(0x26),
+            Op::TrapOutu16(_) => Some(0x27),
+            Op::TrapOuti16(_) => Some(0x28),
+            Op::TrapDumpRegisters(_) => Some(0x2C),
+            Op::TrapInstructionCount(_) => Some(0x2D),
+            Op::TrapIni16(_) => Some(0x2E),
+            Op::TrapFlush(_) => Some(0x2F),
+            _ => None,
+        }
+    }
+
+    /// Run the instruction, an extension trap stops the VM with [`VmError::BadTrap`] under
+    /// [`crate::VmConfig::strict`]
+    pub(crate) fn execute<R: Read, W: Write>(&self, vm: &mut VM<R, W>) {
+        if let Some(vector) = self.extension_vector().filter(|_| vm.config.strict) {
+            vm.fault = Some(RunResult::Error(VmError::BadTrap(vector)));
+            return;
+        }
+        match self {
+            Op::Br(i) => i.execute(vm),
+            Op::AddConst(i) => i.execute(vm),
+            Op::AddReg(i) => i.execute(vm),
+            Op::Ld(i) => i.execute(vm),
+            Op::St(i) => i.execute(vm),
+            Op::Jsr(i) => i.execute(vm),
+            Op::Jsrr(i) => i.execute(vm),
+            Op::AndConst(i) => i.execute(vm),
+            Op::AndReg(i) => i.execute(vm),
+            Op::Ldr(i) => i.execute(vm),
+            Op::Str(i) => i.execute(vm),
+            Op::Rti(i) => i.execute(vm),
+            Op::Not(i) => i.execute(vm),
+            Op::Ldi(i) => i.execute(vm),
+            Op::Sti(i) => i.execute(vm),
+            Op::Jmp(i) => i.execute(vm),
+            Op::Reserved(i) => i.execute(vm),
+            Op::Lea(i) => i.execute(vm),
+            Op::TrapGetC(i) => i.execute(vm),
+            Op::TrapOutC(i) => i.execute(vm),
+            Op::TrapPuts(i) => i.execute(vm),
+            Op::TrapIn(i) => i.execute(vm),
+            Op::TrapPutsp(i) => i.execute(vm),
+            Op::TrapHalt(i) => i.execute(vm),
+            Op::TrapInu16(i) => i.execute(vm),
+            Op::TrapOutu16(i) => i.execute(vm),
+            Op::TrapOuti16(i) => i.execute(vm),
+            Op::TrapDumpRegisters(i) => i.execute(vm),
+            Op::TrapInstructionCount(i) => i.execute(vm),
+            Op::TrapIni16(i) => i.execute(vm),
+            Op::TrapFlush(i) => i.execute(vm),
+            Op::TrapVector(i) => i.execute(vm),
+        }
+    }
 }
 
 #[derive(Debug)]
-struct AddConst {
+pub(crate) struct AddConst {
     dr: Reg,
     sr: Reg,
     imm5: u16,
@@ -122,8 +258,8 @@ where
     W: Write,
 {
     fn execute(&self, vm: &mut VM<R, W>) {
-        let result = vm.registers[&self.sr].wrapping_add(sext(self.imm5, 5));
-        vm.registers.insert(self.dr, result);
+        let result = vm.reg(self.sr).wrapping_add(sext(self.imm5, 5));
+        vm.set_reg(self.dr, result);
         vm.set_nzp(&self.dr);
     }
 }
@@ -139,7 +275,7 @@ impl From<u16> for AddConst {
 }
 
 #[derive(Debug)]
-struct AddReg {
+pub(crate) struct AddReg {
     dr: Reg,
     sr1: Reg,
     sr2: Reg,
@@ -151,8 +287,8 @@ where
     W: Write,
 {
     fn execute(&self, vm: &mut VM<R, W>) {
-        let result = vm.registers[&self.sr1].wrapping_add(vm.registers[&self.sr2]);
-        vm.registers.insert(self.dr, result);
+        let result = vm.reg(self.sr1).wrapping_add(vm.reg(self.sr2));
+        vm.set_reg(self.dr, result);
         vm.set_nzp(&self.dr);
     }
 }
@@ -168,7 +304,7 @@ impl From<u16> for AddReg {
 }
 
 #[derive(Debug)]
-struct AndConst {
+pub(crate) struct AndConst {
     dr: Reg,
     sr: Reg,
     imm5: u16,
@@ -180,8 +316,8 @@ where
     W: Write,
 {
     fn execute(&self, vm: &mut VM<R, W>) {
-        let result = vm.registers[&self.sr] & sext(self.imm5, 5);
-        vm.registers.insert(self.dr, result);
+        let result = vm.reg(self.sr) & sext(self.imm5, 5);
+        vm.set_reg(self.dr, result);
         vm.set_nzp(&self.dr);
     }
 }
@@ -197,7 +333,7 @@ impl From<u16> for AndConst {
 }
 
 #[derive(Debug)]
-struct AndReg {
+pub(crate) struct AndReg {
     dr: Reg,
     sr1: Reg,
     sr2: Reg,
@@ -209,8 +345,8 @@ where
     W: Write,
 {
     fn execute(&self, vm: &mut VM<R, W>) {
-        let result = vm.registers[&self.sr1] & vm.registers[&self.sr2];
-        vm.registers.insert(self.dr, result);
+        let result = vm.reg(self.sr1) & vm.reg(self.sr2);
+        vm.set_reg(self.dr, result);
         vm.set_nzp(&self.dr);
     }
 }
@@ -226,7 +362,7 @@ impl From<u16> for AndReg {
 }
 
 #[derive(Debug)]
-struct Ld {
+pub(crate) struct Ld {
     dr: Reg,
     offset9: u16,
 }
@@ -239,8 +375,8 @@ where
     fn execute(&self, vm: &mut VM<R, W>) {
         let rpc = vm.get_rpc();
         let address = rpc.wrapping_add(sext(self.offset9, 9));
-        let result = vm.memory.read(address);
-        vm.registers.insert(self.dr, result);
+        let result = vm.mem_read(address);
+        vm.set_reg(self.dr, result);
         vm.set_nzp(&self.dr);
     }
 }
@@ -255,7 +391,7 @@ impl From<u16> for Ld {
 }
 
 #[derive(Debug)]
-struct Ldi {
+pub(crate) struct Ldi {
     dr: Reg,
     offset9: u16,
 }
@@ -268,9 +404,9 @@ where
     fn execute(&self, vm: &mut VM<R, W>) {
         let rpc = vm.get_rpc();
         let address1 = rpc.wrapping_add(sext(self.offset9, 9));
-        let address2 = vm.memory.read(address1);
-        let result = vm.memory.read(address2);
-        vm.registers.insert(self.dr, result);
+        let address2 = vm.mem_read(address1);
+        let result = vm.mem_read(address2);
+        vm.set_reg(self.dr, result);
         vm.set_nzp(&self.dr);
     }
 }
@@ -285,7 +421,7 @@ impl From<u16> for Ldi {
 }
 
 #[derive(Debug)]
-struct Ldr {
+pub(crate) struct Ldr {
     dr: Reg,
     base: Reg,
     offset6: u16,
@@ -297,9 +433,9 @@ where
     W: Write,
 {
     fn execute(&self, vm: &mut VM<R, W>) {
-        let address = vm.registers[&self.base].wrapping_add(sext(self.offset6, 6));
-        let result = vm.memory.read(address);
-        vm.registers.insert(self.dr, result);
+        let address = vm.reg(self.base).wrapping_add(sext(self.offset6, 6));
+        let result = vm.mem_read(address);
+        vm.set_reg(self.dr, result);
         vm.set_nzp(&self.dr);
     }
 }
@@ -315,7 +451,7 @@ impl From<u16> for Ldr {
 }
 
 #[derive(Debug)]
-struct Lea {
+pub(crate) struct Lea {
     dr: Reg,
     offset9: u16,
 }
@@ -328,7 +464,7 @@ where
     fn execute(&self, vm: &mut VM<R, W>) {
         let rpc = vm.get_rpc();
         let address = rpc.wrapping_add(sext(self.offset9, 9));
-        vm.registers.insert(self.dr, address);
+        vm.set_reg(self.dr, address);
         vm.set_nzp(&self.dr);
     }
 }
@@ -342,7 +478,7 @@ impl From<u16> for Lea {
 }
 
 #[derive(Debug)]
-struct St {
+pub(crate) struct St {
     sr: Reg,
     offset9: u16,
 }
@@ -355,8 +491,8 @@ where
     fn execute(&self, vm: &mut VM<R, W>) {
         let rpc = vm.get_rpc();
         let address = rpc.wrapping_add(sext(self.offset9, 9));
-        let value = vm.registers[&self.sr];
-        vm.memory.write(address, value);
+        let value = vm.reg(self.sr);
+        vm.mem_write(address, value);
     }
 }
 
@@ -369,7 +505,7 @@ impl From<u16> for St {
 }
 
 #[derive(Debug)]
-struct Sti {
+pub(crate) struct Sti {
     sr: Reg,
     offset9: u16,
 }
@@ -382,9 +518,9 @@ where
     fn execute(&self, vm: &mut VM<R, W>) {
         let rpc = vm.get_rpc();
         let address1 = rpc.wrapping_add(sext(self.offset9, 9));
-        let address2 = vm.memory.read(address1);
-        let value = vm.registers[&self.sr];
-        vm.memory.write(address2, value);
+        let address2 = vm.mem_read(address1);
+        let value = vm.reg(self.sr);
+        vm.mem_write(address2, value);
     }
 }
 
@@ -397,7 +533,7 @@ impl From<u16> for Sti {
 }
 
 #[derive(Debug)]
-struct Str {
+pub(crate) struct Str {
     sr: Reg,
     base: Reg,
     offset6: u16,
@@ -409,9 +545,9 @@ where
     W: Write,
 {
     fn execute(&self, vm: &mut VM<R, W>) {
-        let address = vm.registers[&self.base].wrapping_add(sext(self.offset6, 6));
-        let value = vm.registers[&self.sr];
-        vm.memory.write(address, value);
+        let address = vm.reg(self.base).wrapping_add(sext(self.offset6, 6));
+        let value = vm.reg(self.sr);
+        vm.mem_write(address, value);
     }
 }
 
@@ -425,7 +561,7 @@ impl From<u16> for Str {
 }
 
 #[derive(Debug)]
-struct Not {
+pub(crate) struct Not {
     dr: Reg,
     sr: Reg,
 }
@@ -436,8 +572,8 @@ where
     W: Write,
 {
     fn execute(&self, vm: &mut VM<R, W>) {
-        let result = !vm.registers[&self.sr];
-        vm.registers.insert(self.dr, result);
+        let result = !vm.reg(self.sr);
+        vm.set_reg(self.dr, result);
         vm.set_nzp(&self.dr);
     }
 }
@@ -451,7 +587,7 @@ impl From<u16> for Not {
 }
 
 #[derive(Debug)]
-struct Jmp {
+pub(crate) struct Jmp {
     base: Reg,
 }
 
@@ -461,8 +597,12 @@ where
     W: Write,
 {
     fn execute(&self, vm: &mut VM<R, W>) {
-        let new_rpc = vm.registers[&self.base];
-        vm.registers.insert(Reg::RPC, new_rpc);
+        if self.base == Reg::R7 {
+            // RET
+            vm.call_stack.pop();
+        }
+        let new_rpc = vm.reg(self.base);
+        vm.set_reg(Reg::RPC, new_rpc);
     }
 }
 
@@ -474,7 +614,7 @@ impl From<u16> for Jmp {
 }
 
 #[derive(Debug)]
-struct Jsrr {
+pub(crate) struct Jsrr {
     base: Reg,
 }
 
@@ -485,9 +625,10 @@ where
 {
     fn execute(&self, vm: &mut VM<R, W>) {
         let rpc = vm.get_rpc();
-        vm.registers.insert(Reg::R7, rpc);
-        let new_rpc = vm.registers[&self.base];
-        vm.registers.insert(Reg::RPC, new_rpc);
+        vm.call_stack.push(rpc);
+        vm.set_reg(Reg::R7, rpc);
+        let new_rpc = vm.reg(self.base);
+        vm.set_reg(Reg::RPC, new_rpc);
     }
 }
 
@@ -499,7 +640,7 @@ impl From<u16> for Jsrr {
 }
 
 #[derive(Debug)]
-struct Jsr {
+pub(crate) struct Jsr {
     offset11: u16,
 }
 
@@ -510,9 +651,10 @@ where
 {
     fn execute(&self, vm: &mut VM<R, W>) {
         let rpc = vm.get_rpc();
-        vm.registers.insert(Reg::R7, rpc);
+        vm.call_stack.push(rpc);
+        vm.set_reg(Reg::R7, rpc);
         let new_rpc = rpc.wrapping_add(sext(self.offset11, 11));
-        vm.registers.insert(Reg::RPC, new_rpc);
+        vm.set_reg(Reg::RPC, new_rpc);
     }
 }
 
@@ -524,7 +666,7 @@ impl From<u16> for Jsr {
 }
 
 #[derive(Debug)]
-struct Br {
+pub(crate) struct Br {
     offset9: u16,
     nzp: u16,
 }
@@ -536,9 +678,8 @@ where
 {
     fn execute(&self, vm: &mut VM<R, W>) {
         let rpc = vm.get_rpc();
-        if self.nzp & vm.registers[&Reg::RCond] > 0 {
-            vm.registers
-                .insert(Reg::RPC, rpc.wrapping_add(sext(self.offset9, 9)));
+        if self.nzp & vm.reg(Reg::RCond) > 0 {
+            vm.set_reg(Reg::RPC, rpc.wrapping_add(sext(self.offset9, 9)));
         }
     }
 }
@@ -551,8 +692,56 @@ impl From<u16> for Br {
     }
 }
 
+/// Return from a trap routine entered with [`TrapReturn::Rti`] or from an interrupt: pop the
+/// PC then the PSR. Only allowed in supervisor mode
 #[derive(Debug)]
-struct TrapGetC;
+pub(crate) struct Rti;
+
+impl<R, W> Instruction<R, W> for Rti
+where
+    R: Read,
+    W: Write,
+{
+    fn execute(&self, vm: &mut VM<R, W>) {
+        if vm.user_mode() {
+            vm.fault = Some(RunResult::Error(VmError::PrivilegeViolation(vm.current_pc)));
+            return;
+        }
+        let Some(pc) = vm.pop() else {
+            return;
+        };
+        let Some(psr) = vm.pop() else {
+            return;
+        };
+        vm.set_reg(Reg::RPC, pc);
+        vm.set_psr(psr);
+    }
+}
+
+/// Opcode 1101, illegal unless a handler is set with [`VM::set_custom_opcode_handler`]
+#[derive(Debug)]
+pub(crate) struct Reserved {
+    instruction: u16,
+}
+
+impl<R, W> Instruction<R, W> for Reserved
+where
+    R: Read,
+    W: Write,
+{
+    fn execute(&self, vm: &mut VM<R, W>) {
+        let Some(mut handler) = vm.custom_opcode.take() else {
+            vm.fault = Some(RunResult::Error(VmError::IllegalOpcode(self.instruction)));
+            return;
+        };
+        handler(vm, self.instruction);
+        // The handler may have set another handler
+        vm.custom_opcode.get_or_insert(handler);
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct TrapGetC;
 
 impl<R, W> Instruction<R, W> for TrapGetC
 where
@@ -560,18 +749,25 @@ where
     W: Write,
 {
     fn execute(&self, vm: &mut VM<R, W>) {
-        let rpc = vm.get_rpc();
-        vm.registers.insert(Reg::R7, rpc);
+        vm.link_trap();
+
+        let Some(c) = read_input(vm) else {
+            return;
+        };
+        vm.set_reg(Reg::R0, c);
+        if vm.config.compat_reference {
+            vm.set_nzp(&Reg::R0);
+        }
 
-        let mut buf = [0; 1];
-        vm.reader.read(&mut buf).expect("read");
-        let c = buf[0] as u16;
-        vm.registers.insert(Reg::R0, c);
+        if vm.config.echo_getc && c <= 0xFF {
+            vm.output(&[c as u8][..]);
+            vm.flush_output();
+        }
     }
 }
 
 #[derive(Debug)]
-struct TrapOutC;
+pub(crate) struct TrapOutC;
 
 impl<R, W> Instruction<R, W> for TrapOutC
 where
@@ -579,17 +775,16 @@ where
     W: Write,
 {
     fn execute(&self, vm: &mut VM<R, W>) {
-        let rpc = vm.get_rpc();
-        vm.registers.insert(Reg::R7, rpc);
+        vm.link_trap();
 
-        let c = vm.registers[&Reg::R0];
-        vm.writer.write_all(&[c as u8][..]).expect("write_all");
-        vm.writer.flush().expect("Writer flushed");
+        let c = vm.reg(Reg::R0);
+        vm.output(&[c as u8][..]);
+        vm.flush_output();
     }
 }
 
 #[derive(Debug)]
-struct TrapPuts;
+pub(crate) struct TrapPuts;
 
 impl<R, W> Instruction<R, W> for TrapPuts
 where
@@ -597,24 +792,23 @@ where
     W: Write,
 {
     fn execute(&self, vm: &mut VM<R, W>) {
-        let rpc = vm.get_rpc();
-        vm.registers.insert(Reg::R7, rpc);
+        vm.link_trap();
 
-        let address = vm.registers[&Reg::R0];
+        let address = vm.reg(Reg::R0);
 
-        let mut c = vm.memory.read(address);
-        let mut i = 0;
+        let mut c = vm.mem_read(address);
+        let mut i: u16 = 0;
         while c != 0 {
-            vm.writer.write_all(&[c as u8][..]).expect("write_all");
-            i += 1;
-            c = vm.memory.read(address + i);
+            vm.output(&[c as u8][..]);
+            i = i.wrapping_add(1);
+            c = vm.mem_read(address.wrapping_add(i));
         }
-        vm.writer.flush().expect("Writer flushed");
+        vm.flush_output();
     }
 }
 
 #[derive(Debug)]
-struct TrapIn;
+pub(crate) struct TrapIn;
 
 impl<R, W> Instruction<R, W> for TrapIn
 where
@@ -622,20 +816,28 @@ where
     W: Write,
 {
     fn execute(&self, vm: &mut VM<R, W>) {
-        let rpc = vm.get_rpc();
-        vm.registers.insert(Reg::R7, rpc);
+        vm.link_trap();
 
-        let mut buf: [u8; 1] = [0; 1];
-        vm.reader.read(&mut buf).expect("read");
-        let c = buf[0] as u16;
-        vm.registers.insert(Reg::R0, c);
-        vm.writer.write_all(&[c as u8][..]).expect("write_all");
-        vm.writer.flush().expect("Writer flushed");
+        if vm.config.compat_reference {
+            vm.output(b"Enter a character: ");
+            vm.flush_output();
+        }
+        let Some(c) = read_input(vm) else {
+            return;
+        };
+        vm.set_reg(Reg::R0, c);
+        if vm.config.compat_reference {
+            vm.set_nzp(&Reg::R0);
+        }
+        if c <= 0xFF {
+            vm.output(&[c as u8][..]);
+            vm.flush_output();
+        }
     }
 }
 
 #[derive(Debug)]
-struct TrapPutsp;
+pub(crate) struct TrapPutsp;
 
 impl<R, W> Instruction<R, W> for TrapPutsp
 where
@@ -643,27 +845,26 @@ where
     W: Write,
 {
     fn execute(&self, vm: &mut VM<R, W>) {
-        let rpc = vm.get_rpc();
-        vm.registers.insert(Reg::R7, rpc);
+        vm.link_trap();
 
-        let address = vm.registers[&Reg::R0];
+        let address = vm.reg(Reg::R0);
 
-        let mut c = vm.memory.read(address);
-        let mut i = 0;
+        let mut c = vm.mem_read(address);
+        let mut i: u16 = 0;
         while c != 0 {
             let num1: u8 = (c >> 8) as u8;
             let num2: u8 = (0b0000000011111111 & c) as u8;
-            vm.writer.write_all(&[num1, num2][..]).expect("write_all");
+            vm.output(&[num1, num2][..]);
 
-            i += 1;
-            c = vm.memory.read(address + i);
+            i = i.wrapping_add(1);
+            c = vm.mem_read(address.wrapping_add(i));
         }
-        vm.writer.flush().expect("Writer flushed");
+        vm.flush_output();
     }
 }
 
 #[derive(Debug)]
-struct TrapHalt;
+pub(crate) struct TrapHalt;
 
 impl<R, W> Instruction<R, W> for TrapHalt
 where
@@ -671,41 +872,88 @@ where
     W: Write,
 {
     fn execute(&self, vm: &mut VM<R, W>) {
+        if vm.config.compat_reference {
+            vm.link_trap();
+            vm.output(b"HALT\n");
+            vm.flush_output();
+        }
         vm.halt = true;
+        vm.memory.mem[MR_MCR as usize] &= !(1 << 15);
     }
 }
 
+/// Trap without a native handler, dispatched through the trap vector table in memory
 #[derive(Debug)]
-struct TrapInu16;
+pub(crate) struct TrapVector {
+    vect: u8,
+}
 
-impl<R, W> Instruction<R, W> for TrapInu16
+impl<R, W> Instruction<R, W> for TrapVector
 where
     R: Read,
     W: Write,
 {
     fn execute(&self, vm: &mut VM<R, W>) {
+        if vm.config.compat_reference {
+            vm.link_trap();
+            return;
+        }
+
+        let routine = vm.memory.mem[self.vect as usize];
+        if routine == 0 {
+            vm.fault = Some(RunResult::Error(VmError::BadTrap(self.vect)));
+            return;
+        }
+
         let rpc = vm.get_rpc();
-        vm.registers.insert(Reg::R7, rpc);
-
-        let mut buf: [u8; 1] = [0; 1];
-        let mut all_characters = String::from("");
-        let mut character: u8 = 0;
-        while character != 0x0A {
-            // 0x0A: Enter
-            vm.reader.read(&mut buf).expect("read");
-            character = buf[0];
-            if character.is_ascii_digit() {
-                all_characters.push(character as char);
+        match vm.config.trap_return {
+            TrapReturn::Ret => {
+                vm.set_reg(Reg::R7, rpc);
+            }
+            TrapReturn::Rti => {
+                let psr = vm.psr();
+                if vm.push(psr).and_then(|_| vm.push(rpc)).is_none() {
+                    return;
+                }
+            }
+        }
+        vm.set_reg(Reg::RPC, routine);
+    }
+}
+
+/// Read a decimal number ending with Enter or the end of the input in R0, other characters
+/// are skipped. An empty line reads as 0 and values past xFFFF saturate
+#[derive(Debug)]
+pub(crate) struct TrapInu16;
+
+impl<R, W> Instruction<R, W> for TrapInu16
+where
+    R: Read,
+    W: Write,
+{
+    fn execute(&self, vm: &mut VM<R, W>) {
+        vm.link_trap();
+
+        let mut number: u32 = 0;
+        loop {
+            let Some(character) = read_char(vm) else {
+                return;
+            };
+            match character {
+                0 | 0x0A => break, // end of input or Enter
+                c if c.is_ascii_digit() => {
+                    number = (number * 10 + (c - b'0') as u32).min(u16::MAX as u32);
+                }
+                _ => {}
             }
         }
 
-        let number: u16 = u16::from_str_radix(&all_characters, 10).expect("u16 conversion failed");
-        vm.registers.insert(Reg::R0, number);
+        vm.set_reg(Reg::R0, number as u16);
     }
 }
 
 #[derive(Debug)]
-struct TrapOutu16;
+pub(crate) struct TrapOutu16;
 
 impl<R, W> Instruction<R, W> for TrapOutu16
 where
@@ -713,345 +961,646 @@ where
     W: Write,
 {
     fn execute(&self, vm: &mut VM<R, W>) {
-        let rpc = vm.get_rpc();
-        vm.registers.insert(Reg::R7, rpc);
+        vm.link_trap();
 
-        let c = vm.registers[&Reg::R0];
+        let c = vm.reg(Reg::R0);
         let c_string = c.to_string();
         for character in c_string.as_bytes() {
-            vm.writer.write_all(&[*character][..]).expect("write_all");
+            vm.output(&[*character][..]);
         }
-        vm.writer.flush().expect("Writer flushed");
+        vm.flush_output();
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct TrapOuti16;
+
+impl<R, W> Instruction<R, W> for TrapOuti16
+where
+    R: Read,
+    W: Write,
+{
+    fn execute(&self, vm: &mut VM<R, W>) {
+        vm.link_trap();
+
+        let n = vm.register_signed(Reg::R0);
+        vm.output(n.to_string().as_bytes());
+        vm.flush_output();
+    }
+}
+
+/// Debug extension: write the register dump to the output
+#[derive(Debug)]
+pub(crate) struct TrapDumpRegisters;
+
+impl<R, W> Instruction<R, W> for TrapDumpRegisters
+where
+    R: Read,
+    W: Write,
+{
+    fn execute(&self, vm: &mut VM<R, W>) {
+        vm.link_trap();
+
+        let dump = vm.dump_registers();
+        vm.output(format!("{dump}\n").as_bytes());
+        vm.flush_output();
+    }
+}
+
+/// Read a decimal number ending with Enter, possibly negative, in R0 as an i16.
+/// A lone `-` reads as 0 and values past the i16 range saturate
+#[derive(Debug)]
+pub(crate) struct TrapIni16;
+
+impl<R, W> Instruction<R, W> for TrapIni16
+where
+    R: Read,
+    W: Write,
+{
+    fn execute(&self, vm: &mut VM<R, W>) {
+        vm.link_trap();
+
+        let mut negative = false;
+        let mut number: i32 = 0;
+        let mut first = true;
+        loop {
+            let Some(character) = read_char(vm) else {
+                return;
+            };
+            match character {
+                0 | 0x0A => break, // end of input or Enter
+                b'-' if first => negative = true,
+                c if c.is_ascii_digit() => {
+                    number = (number * 10 + (c - b'0') as i32).min(i16::MAX as i32 + 1);
+                }
+                _ => {}
+            }
+            first = false;
+        }
+
+        let number = if negative { -number } else { number };
+        let number = number.clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+        vm.set_reg(Reg::R0, number as u16);
+        vm.set_nzp(&Reg::R0);
+    }
+}
+
+/// Profiling extension: the low 16 bits of the instruction count, this TRAP included, in R0
+#[derive(Debug)]
+pub(crate) struct TrapInstructionCount;
+
+impl<R, W> Instruction<R, W> for TrapInstructionCount
+where
+    R: Read,
+    W: Write,
+{
+    fn execute(&self, vm: &mut VM<R, W>) {
+        vm.link_trap();
+
+        vm.set_reg(Reg::R0, vm.instruction_count() as u16);
+    }
+}
+
+/// Output extension: flush the writer, for guests running with a buffered writer
+#[derive(Debug)]
+pub(crate) struct TrapFlush;
+
+impl<R, W> Instruction<R, W> for TrapFlush
+where
+    R: Read,
+    W: Write,
+{
+    fn execute(&self, vm: &mut VM<R, W>) {
+        vm.link_trap();
+
+        vm.flush_output();
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::unusual_byte_groupings, clippy::field_reassign_with_default)]
 mod tests {
 
     use super::*;
+    use crate::VmConfig;
+
+    // The instructions are executed directly with the default PC of 0x3000: that is the
+    // incremented PC, so they behave as if they were fetched from 0x2FFF.
 
     #[test]
     fn test_exec_add_reg() {
-        let mut vm = VM::default();
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
 
-        vm.registers.insert(Reg::R1, 0b0000000000000100); // 4
-        vm.registers.insert(Reg::R2, 0b0000000000000011); // 3
+        vm.set_reg(Reg::R1, 0b0000000000000100); // 4
+        vm.set_reg(Reg::R2, 0b0000000000000011); // 3
 
-        let op: Box<dyn Instruction<&[u8], Vec<u8>>> = 0b0001_000_001_0_00_010.into();
+        let op = Op::decode(0b0001_000_001_0_00_010);
         op.execute(&mut vm);
-        assert_eq!(vm.registers[&Reg::R0], 0b0000000000000111); // 7
-        assert_eq!(vm.registers[&Reg::RPC], 0x3000);
+        assert_eq!(vm.reg(Reg::R0), 0b0000000000000111); // 7
+        assert_eq!(vm.reg(Reg::RPC), 0x3000);
     }
 
     #[test]
     fn test_exec_add_const() {
-        let mut vm = VM::default();
-        vm.registers.insert(Reg::R3, 0b1111_1111_1111_0111); // -9
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.set_reg(Reg::R3, 0b1111_1111_1111_0111); // -9
 
-        let op: Box<dyn Instruction<&[u8], Vec<u8>>> = 0b0001_000_011_1_00111.into(); // Add R3 + 7
+        let op = Op::decode(0b0001_000_011_1_00111); // Add R3 + 7
         op.execute(&mut vm);
 
-        assert_eq!(vm.registers[&Reg::R0], 0b1111_1111_1111_1110); // -2
-        assert_eq!(vm.registers[&Reg::RPC], 0x3000);
+        assert_eq!(vm.reg(Reg::R0), 0b1111_1111_1111_1110); // -2
+        assert_eq!(vm.reg(Reg::RPC), 0x3000);
     }
 
     #[test]
     fn test_exec_and_reg() {
-        let mut vm = VM::default();
-        vm.registers.insert(Reg::R4, 0b1010101010101010);
-        vm.registers.insert(Reg::R5, 0b0101010101010101);
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.set_reg(Reg::R4, 0b1010101010101010);
+        vm.set_reg(Reg::R5, 0b0101010101010101);
 
-        let op: Box<dyn Instruction<&[u8], Vec<u8>>> = 0b0101000001000010.into();
+        let op = Op::decode(0b0101000001000010);
         op.execute(&mut vm);
 
-        assert_eq!(vm.registers[&Reg::R0], 0);
-        assert_eq!(vm.registers[&Reg::RPC], 0x3000);
+        assert_eq!(vm.reg(Reg::R0), 0);
+        assert_eq!(vm.reg(Reg::RPC), 0x3000);
     }
 
     #[test]
     fn test_exec_and_const() {
-        let mut vm = VM::default();
-        vm.registers.insert(Reg::R6, 0b1010101010101010);
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.set_reg(Reg::R6, 0b1010101010101010);
 
-        let op: Box<dyn Instruction<&[u8], Vec<u8>>> = 0b0101_000_110_110101.into(); // AndConst Dr=R0 Sr=R6 const=110101
+        let op = Op::decode(0b0101_000_110_110101); // AndConst Dr=R0 Sr=R6 const=110101
         op.execute(&mut vm);
 
-        assert_eq!(vm.registers[&Reg::R0], 0b1010101010100000);
-        assert_eq!(vm.registers[&Reg::RPC], 0x3000);
+        assert_eq!(vm.reg(Reg::R0), 0b1010101010100000);
+        assert_eq!(vm.reg(Reg::RPC), 0x3000);
     }
 
     #[test]
     fn test_exec_ld() {
-        let mut vm = VM::default();
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
         vm.memory.write(0x2FFF, 718);
 
-        let op: Box<dyn Instruction<&[u8], Vec<u8>>> = 0b0010_110_111111111.into(); // Ld Dr=R6 offset=-1
+        let op = Op::decode(0b0010_110_111111111); // Ld Dr=R6 offset=-1
         op.execute(&mut vm);
 
-        assert_eq!(vm.registers[&Reg::R6], 718);
-        assert_eq!(vm.registers[&Reg::RPC], 0x3000);
+        assert_eq!(vm.reg(Reg::R6), 718);
+        assert_eq!(vm.reg(Reg::RPC), 0x3000);
     }
 
     #[test]
     fn test_exec_ldi() {
-        let mut vm = VM::default();
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
         vm.memory.write(0x2FFF, 7);
         vm.memory.write(7, 18);
 
-        let op: Box<dyn Instruction<&[u8], Vec<u8>>> = 0b1010_101_111111111.into(); // Ldi Dr=R5 offset=-1
+        let op = Op::decode(0b1010_101_111111111); // Ldi Dr=R5 offset=-1
         op.execute(&mut vm);
 
-        assert_eq!(vm.registers[&Reg::R5], 18);
-        assert_eq!(vm.registers[&Reg::RPC], 0x3000);
+        assert_eq!(vm.reg(Reg::R5), 18);
+        assert_eq!(vm.reg(Reg::RPC), 0x3000);
     }
 
     #[test]
     fn test_exec_ldr() {
-        let mut vm = VM::default();
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
         vm.memory.write(0xFFFF, 718);
-        vm.registers.insert(Reg::R7, 0xFFFE);
+        vm.set_reg(Reg::R7, 0xFFFE);
 
-        let op: Box<dyn Instruction<&[u8], Vec<u8>>> = 0b0110_010_111_000001.into(); // Ldr Dr=R2 baseR=R7 offset=1
+        let op = Op::decode(0b0110_010_111_000001); // Ldr Dr=R2 baseR=R7 offset=1
         op.execute(&mut vm);
 
-        assert_eq!(vm.registers[&Reg::R2], 718);
-        assert_eq!(vm.registers[&Reg::RPC], 0x3000);
+        assert_eq!(vm.reg(Reg::R2), 718);
+        assert_eq!(vm.reg(Reg::RPC), 0x3000);
     }
 
     #[test]
     fn test_exec_lea() {
-        let mut vm = VM::default();
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
 
-        let op: Box<dyn Instruction<&[u8], Vec<u8>>> = 0b1110_011_111111111.into(); // Lea Dr=R3 offset=-1
+        let op = Op::decode(0b1110_011_111111111); // Lea Dr=R3 offset=-1
         op.execute(&mut vm);
 
-        assert_eq!(vm.registers[&Reg::R3], 0x2FFF);
-        assert_eq!(vm.registers[&Reg::RPC], 0x3000);
+        assert_eq!(vm.reg(Reg::R3), 0x2FFF);
+        assert_eq!(vm.reg(Reg::RPC), 0x3000);
     }
 
     #[test]
     fn test_exec_not() {
-        let mut vm = VM::default();
-        vm.registers.insert(Reg::R1, 0xF0F0);
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.set_reg(Reg::R1, 0xF0F0);
 
-        let op: Box<dyn Instruction<&[u8], Vec<u8>>> = 0b1001_000_001_111111.into(); // Not Dr=R0 Sr=R1
+        let op = Op::decode(0b1001_000_001_111111); // Not Dr=R0 Sr=R1
         op.execute(&mut vm);
 
-        assert_eq!(vm.registers[&Reg::R0], 0x0F0F);
-        assert_eq!(vm.registers[&Reg::RPC], 0x3000);
+        assert_eq!(vm.reg(Reg::R0), 0x0F0F);
+        assert_eq!(vm.reg(Reg::RPC), 0x3000);
     }
 
     #[test]
     fn test_exec_st() {
-        let mut vm = VM::default();
-        vm.registers.insert(Reg::R2, 718);
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.set_reg(Reg::R2, 718);
 
-        let op: Box<dyn Instruction<&[u8], Vec<u8>>> = 0b0011_010_111111111.into(); // St Sr=R2 offset=-1
+        let op = Op::decode(0b0011_010_111111111); // St Sr=R2 offset=-1
         op.execute(&mut vm);
 
-        assert_eq!(vm.memory.read(0x2FFF), 718);
-        assert_eq!(vm.registers[&Reg::RPC], 0x3000);
+        assert_eq!(vm.mem_read(0x2FFF), 718);
+        assert_eq!(vm.reg(Reg::RPC), 0x3000);
     }
 
     #[test]
     fn test_exec_sti() {
-        let mut vm = VM::default();
-        vm.registers.insert(Reg::R3, 718);
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.set_reg(Reg::R3, 718);
         vm.memory.write(0x2FFF, 0xFFFF);
 
-        let op: Box<dyn Instruction<&[u8], Vec<u8>>> = 0b1011_011_111111111.into(); // Sti Sr=R3 offset=-1
+        let op = Op::decode(0b1011_011_111111111); // Sti Sr=R3 offset=-1
         op.execute(&mut vm);
 
-        assert_eq!(vm.memory.read(0xFFFF), 718);
-        assert_eq!(vm.registers[&Reg::RPC], 0x3000);
+        assert_eq!(vm.mem_read(0xFFFF), 718);
+        assert_eq!(vm.reg(Reg::RPC), 0x3000);
     }
 
     #[test]
     fn test_exec_str() {
-        let mut vm = VM::default();
-        vm.registers.insert(Reg::R4, 718);
-        vm.registers.insert(Reg::R5, 0xFF00);
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.set_reg(Reg::R4, 718);
+        vm.set_reg(Reg::R5, 0xFF00);
 
-        let op: Box<dyn Instruction<&[u8], Vec<u8>>> = 0b0111_100_101_111111.into(); // Str Sr=R4 BaseR=R5 offset=-1
+        let op = Op::decode(0b0111_100_101_111111); // Str Sr=R4 BaseR=R5 offset=-1
         op.execute(&mut vm);
 
-        assert_eq!(vm.memory.read(0xFEFF), 718);
-        assert_eq!(vm.registers[&Reg::RPC], 0x3000);
+        assert_eq!(vm.mem_read(0xFEFF), 718);
+        assert_eq!(vm.reg(Reg::RPC), 0x3000);
     }
 
     #[test]
     fn test_exec_jmp() {
-        let mut vm = VM::default();
-        vm.registers.insert(Reg::R6, 0xFF00);
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.set_reg(Reg::R6, 0xFF00);
 
-        let op: Box<dyn Instruction<&[u8], Vec<u8>>> = 0b1100_000_110_000000.into(); // Jmp BaseR=R6
+        let op = Op::decode(0b1100_000_110_000000); // Jmp BaseR=R6
         op.execute(&mut vm);
 
-        assert_eq!(vm.registers[&Reg::RPC], 0xFF00);
+        assert_eq!(vm.reg(Reg::RPC), 0xFF00);
     }
 
     #[test]
     fn test_exec_jsrr() {
-        let mut vm = VM::default();
-        vm.registers.insert(Reg::R0, 0xFF00);
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.set_reg(Reg::R0, 0xFF00);
 
-        let op: Box<dyn Instruction<&[u8], Vec<u8>>> = 0b0100_0_00_000_000000.into(); // JsrR BaseR=R0
+        let op = Op::decode(0b0100_0_00_000_000000); // JsrR BaseR=R0
         op.execute(&mut vm);
 
-        assert_eq!(vm.registers[&Reg::RPC], 0xFF00);
-        assert_eq!(vm.registers[&Reg::R7], 0x3000);
+        assert_eq!(vm.reg(Reg::RPC), 0xFF00);
+        assert_eq!(vm.reg(Reg::R7), 0x3000);
     }
 
     #[test]
     fn test_exec_jsr() {
-        let mut vm = VM::default();
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
 
-        let op: Box<dyn Instruction<&[u8], Vec<u8>>> = 0b0100_1_11111111111.into(); // Jsr offset=-1
+        let op = Op::decode(0b0100_1_11111111111); // Jsr offset=-1
         op.execute(&mut vm);
 
-        assert_eq!(vm.registers[&Reg::RPC], 0x3000 - 1);
-        assert_eq!(vm.registers[&Reg::R7], 0x3000);
+        assert_eq!(vm.reg(Reg::RPC), 0x3000 - 1);
+        assert_eq!(vm.reg(Reg::R7), 0x3000);
     }
 
     #[test]
     fn test_exec_br() {
-        let mut vm = VM::default();
-        vm.registers.insert(Reg::RCond, 0b0000000000000100);
-        let op: Box<dyn Instruction<&[u8], Vec<u8>>> = 0b0000_100_111111111.into(); // BrN offset=-1
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.set_reg(Reg::RCond, 0b0000000000000100);
+        let op = Op::decode(0b0000_100_111111111); // BrN offset=-1
+        op.execute(&mut vm);
+        assert_eq!(vm.reg(Reg::RPC), 0x3000 - 1);
+
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.set_reg(Reg::RCond, 0b0000000000000100);
+        let op = Op::decode(0b0000_011_111111111); // BrN offset=-1
+        op.execute(&mut vm);
+        assert_eq!(vm.reg(Reg::RPC), 0x3000);
+
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.set_reg(Reg::RCond, 0b0000000000000010);
+        let op = Op::decode(0b0000_010_111111111); // BrZ offset=-1
         op.execute(&mut vm);
-        assert_eq!(vm.registers[&Reg::RPC], 0x3000 - 1);
+        assert_eq!(vm.reg(Reg::RPC), 0x3000 - 1);
 
-        let mut vm = VM::default();
-        vm.registers.insert(Reg::RCond, 0b0000000000000100);
-        let op: Box<dyn Instruction<&[u8], Vec<u8>>> = 0b0000_011_111111111.into(); // BrN offset=-1
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.set_reg(Reg::RCond, 0b0000000000000010);
+        let op = Op::decode(0b0000_101_111111111); // BrZ offset=-1
         op.execute(&mut vm);
-        assert_eq!(vm.registers[&Reg::RPC], 0x3000);
+        assert_eq!(vm.reg(Reg::RPC), 0x3000);
 
-        let mut vm = VM::default();
-        vm.registers.insert(Reg::RCond, 0b0000000000000010);
-        let op: Box<dyn Instruction<&[u8], Vec<u8>>> = 0b0000_010_111111111.into(); // BrZ offset=-1
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.set_reg(Reg::RCond, 0b0000000000000001);
+        let op = Op::decode(0b0000_001_111111111); // BrP offset=-1
         op.execute(&mut vm);
-        assert_eq!(vm.registers[&Reg::RPC], 0x3000 - 1);
+        assert_eq!(vm.reg(Reg::RPC), 0x3000 - 1);
 
-        let mut vm = VM::default();
-        vm.registers.insert(Reg::RCond, 0b0000000000000010);
-        let op: Box<dyn Instruction<&[u8], Vec<u8>>> = 0b0000_101_111111111.into(); // BrZ offset=-1
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.set_reg(Reg::RCond, 0b0000000000000001);
+        let op = Op::decode(0b0000_110_111111111); // BrP offset=-1
         op.execute(&mut vm);
-        assert_eq!(vm.registers[&Reg::RPC], 0x3000);
+        assert_eq!(vm.reg(Reg::RPC), 0x3000);
+    }
+
+    #[test]
+    fn test_exec_rti() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.set_reg(Reg::R6, 0x2FFE);
+        vm.memory.write(0x2FFE, 0x3050); // saved PC
+        vm.memory.write(0x2FFF, 0x8302); // saved PSR: user mode, priority 3, Z
 
-        let mut vm = VM::default();
-        vm.registers.insert(Reg::RCond, 0b0000000000000001);
-        let op: Box<dyn Instruction<&[u8], Vec<u8>>> = 0b0000_001_111111111.into(); // BrP offset=-1
+        let op = Op::decode(0b1000_000000000000); // Rti
         op.execute(&mut vm);
-        assert_eq!(vm.registers[&Reg::RPC], 0x3000 - 1);
 
-        let mut vm = VM::default();
-        vm.registers.insert(Reg::RCond, 0b0000000000000001);
-        let op: Box<dyn Instruction<&[u8], Vec<u8>>> = 0b0000_110_111111111.into(); // BrP offset=-1
+        assert_eq!(vm.reg(Reg::RPC), 0x3050);
+        assert_eq!(vm.reg(Reg::R6), 0x3000);
+        assert!(vm.user_mode());
+        assert_eq!(vm.priority(), 3);
+        assert_eq!(vm.psr(), 0x8302);
+
+        // a second RTI now runs in user mode
+        vm.current_pc = 0x3050;
         op.execute(&mut vm);
-        assert_eq!(vm.registers[&Reg::RPC], 0x3000);
+        assert_eq!(
+            vm.fault,
+            Some(RunResult::Error(VmError::PrivilegeViolation(0x3050)))
+        );
+        assert_eq!(vm.reg(Reg::RPC), 0x3050);
+        assert_eq!(vm.reg(Reg::R6), 0x3000);
     }
 
     #[test]
     fn test_exec_trap_getc() {
-        let mut vm = VM::default();
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
         vm.reader = &[0x41, 0x0A][..];
 
-        let op: Box<dyn Instruction<&[u8], Vec<u8>>> = 0b1111000000100000.into();
+        let op = Op::decode(0b1111000000100000);
         op.execute(&mut vm);
 
-        assert_eq!(vm.registers[&Reg::R0], 0x41); // 0x41 == A
-        assert_eq!(vm.registers[&Reg::R7], 0x3000);
+        assert_eq!(vm.reg(Reg::R0), 0x41); // 0x41 == A
+        assert_eq!(vm.reg(Reg::R7), 0x3000);
+    }
+
+    #[test]
+    fn test_exec_trap_getc_echo() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.reader = &[0x41, 0x42][..];
+
+        let getc = Op::decode(0b1111000000100000);
+        getc.execute(&mut vm);
+        assert_eq!(vm.writer, vec![]); // GETC doesn't echo
+
+        let op_in = Op::decode(0b1111000000100011);
+        op_in.execute(&mut vm);
+        assert_eq!(vm.writer, vec![0x42]); // IN does
+
+        vm.set_config(VmConfig {
+            echo_getc: true,
+            ..VmConfig::default()
+        });
+        vm.reader = &[0x43][..];
+        getc.execute(&mut vm);
+        assert_eq!(vm.writer, vec![0x42, 0x43]);
+    }
+
+    #[test]
+    fn test_exec_trap_getc_eof() {
+        let getc = Op::decode(0b1111000000100000);
+        let op_in = Op::decode(0b1111000000100011);
+
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.set_reg(Reg::R0, 0x1234);
+        getc.execute(&mut vm);
+        assert_eq!(vm.reg(Reg::R0), 0);
+
+        vm.set_config(VmConfig {
+            eof_behavior: EofBehavior::ReturnNegativeOne,
+            ..VmConfig::default()
+        });
+        getc.execute(&mut vm);
+        assert_eq!(vm.reg(Reg::R0), 0xFFFF);
+        vm.set_reg(Reg::R0, 0);
+        op_in.execute(&mut vm);
+        assert_eq!(vm.reg(Reg::R0), 0xFFFF);
+        assert_eq!(vm.writer, vec![]);
+        assert!(!vm.halt);
+
+        vm.set_config(VmConfig {
+            eof_behavior: EofBehavior::Halt,
+            ..VmConfig::default()
+        });
+        vm.set_reg(Reg::R0, 0x1234);
+        getc.execute(&mut vm);
+        assert!(vm.halt);
+        assert_eq!(vm.reg(Reg::R0), 0x1234);
     }
 
     #[test]
     fn test_exec_trap_outc() {
-        let mut vm = VM::default();
-        vm.registers.insert(Reg::R0, 0x41);
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.set_reg(Reg::R0, 0x41);
 
-        let op: Box<dyn Instruction<&[u8], Vec<u8>>> = 0b1111000000100001.into();
+        let op = Op::decode(0b1111000000100001);
         op.execute(&mut vm);
 
         assert_eq!(vm.writer, vec![0x41]);
-        assert_eq!(vm.registers[&Reg::R7], 0x3000);
+        assert_eq!(vm.reg(Reg::R7), 0x3000);
     }
 
     #[test]
     fn test_exec_trap_puts() {
-        let mut vm = VM::default();
-        vm.registers.insert(Reg::R0, 718);
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.set_reg(Reg::R0, 718);
         vm.memory.mem[718] = 0x41; // A
         vm.memory.mem[719] = 0x42; // B
         vm.memory.mem[720] = 0x43; // C
         vm.memory.mem[721] = 0x0;
 
-        let op: Box<dyn Instruction<&[u8], Vec<u8>>> = 0b1111000000100010.into();
+        let op = Op::decode(0b1111000000100010);
         op.execute(&mut vm);
 
         assert_eq!(vm.writer, vec![0x41, 0x42, 0x43]);
-        assert_eq!(vm.registers[&Reg::R7], 0x3000);
+        assert_eq!(vm.reg(Reg::R7), 0x3000);
+
+        // A string ending at xFFFF, its terminator wraps to x0000
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.set_reg(Reg::R0, 0xFFFF);
+        vm.memory.mem[0xFFFF] = 0x41;
+        op.execute(&mut vm);
+        assert_eq!(vm.writer, vec![0x41]);
     }
 
     #[test]
     fn test_exec_trap_in() {
-        let mut vm = VM::default();
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
         vm.reader = &[0x41, 0x0A][..];
 
-        let op: Box<dyn Instruction<&[u8], Vec<u8>>> = 0b1111000000100011.into();
+        let op = Op::decode(0b1111000000100011);
         op.execute(&mut vm);
 
-        assert_eq!(vm.registers[&Reg::R0], 0x41); // 0x41 == A
+        assert_eq!(vm.reg(Reg::R0), 0x41); // 0x41 == A
         assert_eq!(vm.writer, vec![0x41]);
-        assert_eq!(vm.registers[&Reg::R7], 0x3000);
+        assert_eq!(vm.reg(Reg::R7), 0x3000);
     }
 
     #[test]
     fn test_exec_trap_in_u16() {
-        let mut vm = VM::default();
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
         vm.reader = &[0x32, 0x35, 0x35, 0x0A][..]; // 255 Enter
 
-        let op: Box<dyn Instruction<&[u8], Vec<u8>>> = 0b1111000000100110.into();
+        let op = Op::decode(0b1111000000100110);
         op.execute(&mut vm);
 
-        assert_eq!(vm.registers[&Reg::R0], 255); // R0 contains 255
-        assert_eq!(vm.registers[&Reg::R7], 0x3000);
+        assert_eq!(vm.reg(Reg::R0), 255); // R0 contains 255
+        assert_eq!(vm.reg(Reg::R7), 0x3000);
+
+        for (input, expected) in [
+            (&b"12"[..], 12), // end of input without Enter
+            (b"", 0),
+            (b"\n", 0),
+            (b"a1b2\n", 12),
+            (b"99999\n", 0xFFFF),
+        ] {
+            let mut vm = VM::<&[u8], Vec<u8>>::default();
+            vm.reader = input;
+            op.execute(&mut vm);
+            assert_eq!(vm.reg(Reg::R0), expected);
+        }
     }
 
     #[test]
     fn test_exec_trap_out_u16() {
-        let mut vm = VM::default();
-        vm.registers.insert(Reg::R0, 255);
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.set_reg(Reg::R0, 255);
 
-        let op: Box<dyn Instruction<&[u8], Vec<u8>>> = 0b1111000000100111.into();
+        let op = Op::decode(0b1111000000100111);
         op.execute(&mut vm);
 
         assert_eq!(vm.writer, vec![b'2', b'5', b'5']);
-        assert_eq!(vm.registers[&Reg::R7], 0x3000);
+        assert_eq!(vm.reg(Reg::R7), 0x3000);
+    }
+
+    #[test]
+    fn test_exec_trap_out_i16() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.set_reg(Reg::R0, 0xFFFF);
+
+        let op = Op::decode(0b1111000000101000);
+        op.execute(&mut vm);
+        assert_eq!(vm.writer, b"-1".to_vec());
+
+        vm.set_reg(Reg::R0, 0x7FFF);
+        op.execute(&mut vm);
+        assert_eq!(vm.writer, b"-132767".to_vec());
+        assert_eq!(vm.reg(Reg::R7), 0x3000);
     }
 
     #[test]
     fn test_exec_trap_putsp() {
-        let mut vm = VM::default();
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
 
-        vm.registers.insert(Reg::R0, 718);
+        vm.set_reg(Reg::R0, 718);
         vm.memory.mem[718] = 0x4142; // AB
         vm.memory.mem[719] = 0x4344; // CD
         vm.memory.mem[721] = 0x0;
 
-        let op: Box<dyn Instruction<&[u8], Vec<u8>>> = 0b1111000000100100.into();
+        let op = Op::decode(0b1111000000100100);
         op.execute(&mut vm);
 
         assert_eq!(vm.writer, vec![0x41, 0x42, 0x43, 0x44]);
-        assert_eq!(vm.registers[&Reg::R7], 0x3000);
+        assert_eq!(vm.reg(Reg::R7), 0x3000);
+
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.set_reg(Reg::R0, 0xFFFF);
+        vm.memory.mem[0xFFFF] = 0x4142;
+        op.execute(&mut vm);
+        assert_eq!(vm.writer, vec![0x41, 0x42]);
+    }
+
+    #[test]
+    fn test_exec_trap_in_i16() {
+        let op = Op::decode(0b1111000000101110);
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+
+        vm.reader = &b"-42\n"[..];
+        op.execute(&mut vm);
+        assert_eq!(vm.reg(Reg::R0), 0xFFD6);
+        assert_eq!(vm.reg(Reg::RCond), 1 << 2);
+        assert_eq!(vm.reg(Reg::R7), 0x3000);
+
+        vm.reader = &b"-\n"[..];
+        op.execute(&mut vm);
+        assert_eq!(vm.reg(Reg::R0), 0);
+        assert_eq!(vm.reg(Reg::RCond), 1 << 1);
+
+        vm.reader = &b"99999\n-99999\n"[..];
+        op.execute(&mut vm);
+        assert_eq!(vm.reg(Reg::R0), 0x7FFF);
+        assert_eq!(vm.reg(Reg::RCond), 1);
+        op.execute(&mut vm);
+        assert_eq!(vm.reg(Reg::R0), 0x8000);
+
+        vm.reader = &b"17"[..];
+        op.execute(&mut vm);
+        assert_eq!(vm.reg(Reg::R0), 17);
+    }
+
+    #[test]
+    fn test_exec_trap_dump_registers() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.set_reg(Reg::R1, 0x1234);
+
+        let op = Op::decode(0b1111000000101100);
+        op.execute(&mut vm);
+
+        let dump = String::from_utf8(vm.writer.clone()).unwrap();
+        assert!(dump.contains("R1=x1234"));
+        assert!(dump.contains("R7=x3000"));
+        assert!(dump.contains("PC=x3000"));
+    }
+
+    #[test]
+    fn test_exec_trap_dump_registers_strict() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.set_config(VmConfig {
+            strict: true,
+            ..VmConfig::default()
+        });
+
+        let op = Op::decode(0b1111000000101100);
+        op.execute(&mut vm);
+
+        assert_eq!(vm.fault, Some(RunResult::Error(VmError::BadTrap(0x2C))));
+        assert!(vm.writer.is_empty());
+    }
+
+    #[test]
+    fn test_exec_trap_flush() {
+        let mut vm = crate::VmBuilder::new().build(&b""[..], std::io::BufWriter::new(Vec::new()));
+        vm.writer.write_all(b"buffered").unwrap();
+        assert!(vm.writer.get_ref().is_empty());
+
+        let op = Op::decode(0b1111000000101111);
+        op.execute(&mut vm);
+
+        assert_eq!(vm.writer.get_ref(), b"buffered");
+        assert_eq!(vm.reg(Reg::R7), 0x3000);
     }
 
     #[test]
     fn test_exec_trap_halt() {
-        let mut vm = VM::default();
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
 
-        let op: Box<dyn Instruction<&[u8], Vec<u8>>> = 0b1111000000100101.into();
+        let op = Op::decode(0b1111000000100101);
         op.execute(&mut vm);
 
-        assert_eq!(vm.halt, true);
+        assert!(vm.halt);
     }
 }
@@ -1,41 +1,545 @@
-use std::collections::HashMap;
-use std::fmt::Debug;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt::{self, Debug};
 use std::io::{self, Read, Stdout, Write};
+use std::ops::{Range, RangeInclusive};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 pub const PC_START: usize = 0x3000;
+/// Number of general purpose registers, 8 in the LC-3
+#[cfg(not(feature = "extended-registers"))]
+pub const GPR_COUNT: u16 = 8;
+#[cfg(feature = "extended-registers")]
+pub const GPR_COUNT: u16 = 16;
+/// The general purpose registers, then PC and COND
+const REG_COUNT: usize = GPR_COUNT as usize + 2;
 const MR_KBSR: u16 = 0xFE00;
 const MR_KBDR: u16 = 0xFE02;
+const MR_TMR: u16 = 0xFE08;
+const MR_TMI: u16 = 0xFE0A;
+const MR_MCR: u16 = 0xFFFE;
+/// First address of the memory mapped devices
+const DEVICE_PAGE: u16 = 0xFE00;
+/// Device registers that exist, the rest of the device page is unmapped
+const DEVICES: &[u16] = &[MR_KBSR, MR_KBDR, MR_TMR, MR_TMI, MR_MCR];
 
+pub mod assembler;
+mod config;
+mod coverage;
+pub mod disassembler;
 mod instructions;
+mod snapshot;
 pub mod unsafe_zone;
+mod view;
+pub use config::{BusMode, EofBehavior, OutputOverflow, TimerSource, TrapReturn, VmConfig};
+pub use coverage::CoverageReport;
+use disassembler::{disassemble, target};
 use instructions::*;
+pub use snapshot::SnapshotError;
+pub use view::VmView;
 
+/// Reads the terminal through libc's `getchar`, see [`unsafe_zone::disable_input_buffering`]
+#[derive(Debug, Default, Clone, Copy)]
 pub struct LibCReader;
 
 impl Read for LibCReader {
+    /// One byte at a time, a NUL byte is data and only `getchar`'s EOF ends the input
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        let c_u8 = unsafe_zone::get_char();
-        if buf.len() == 0 {
+        if buf.is_empty() {
             return Ok(0);
         }
-        match c_u8 {
-            0 => Ok(0),
+        match unsafe_zone::get_char() {
+            unsafe_zone::EOF => Ok(0),
             c => {
-                buf[0] = c;
+                buf[0] = c as u8;
                 Ok(1)
             }
         }
     }
 }
 
+/// Why a program image couldn't be loaded
+#[derive(Debug, PartialEq, Eq)]
+pub enum LoadError {
+    /// The image ends with half a word
+    OddLength,
+    /// The image isn't meant to be loaded where expected, see [`VM::load_expecting_origin`]
+    OriginMismatch { found: u16, expected: u16 },
+    /// The image couldn't be read
+    Io(io::ErrorKind),
+    /// The image doesn't even have its origin word
+    EmptyImage,
+    /// With [`VmConfig::strict`], the image starting at `origin` runs past xFFFF
+    ImageTooLarge { origin: u16 },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::OddLength => write!(f, "the image has an odd number of bytes"),
+            LoadError::OriginMismatch { found, expected } => write!(
+                f,
+                "the image origin is x{found:04X} but x{expected:04X} was expected"
+            ),
+            LoadError::Io(kind) => write!(f, "I/O error: {kind}"),
+            LoadError::EmptyImage => write!(f, "the image has no origin"),
+            LoadError::ImageTooLarge { origin } => {
+                write!(f, "the image at x{origin:04X} runs past the end of memory")
+            }
+        }
+    }
+}
+
+impl Error for LoadError {}
+
+/// A condition the program can't recover from, the VM stops after the faulting instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmError {
+    /// TRAP with no native handler and a zero entry in the trap vector table, or to an
+    /// extension vector rejected by [`VmConfig::strict`]
+    BadTrap(u8),
+    /// Word with the reserved opcode 1101 and no handler, see
+    /// [`VM::set_custom_opcode_handler`]
+    IllegalOpcode(u16),
+    /// The reader or the writer failed
+    Io(io::ErrorKind),
+    /// Read of an address of the device page with no device, see [`BusMode::Strict`]
+    BusError(u16),
+    /// A push or pop with R6 at the given value would leave the supervisor stack, see
+    /// [`VM::set_supervisor_stack`]
+    StackFault(u16),
+    /// RTI at the given address executed in user mode
+    PrivilegeViolation(u16),
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VmError::BadTrap(vector) => write!(f, "trap x{vector:02X} has no routine installed"),
+            VmError::IllegalOpcode(word) => write!(f, "x{word:04X} has the reserved opcode"),
+            VmError::Io(kind) => write!(f, "I/O error: {kind}"),
+            VmError::BusError(addr) => write!(f, "no device at x{addr:04X}"),
+            VmError::StackFault(sp) => write!(f, "stack overflow or underflow at x{sp:04X}"),
+            VmError::PrivilegeViolation(pc) => write!(f, "x{pc:04X}: RTI in user mode"),
+        }
+    }
+}
+
+impl Error for VmError {}
+
+/// A priority level outside 0-7 was given to [`VM::set_priority`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidPriority(pub u8);
+
+impl fmt::Display for InvalidPriority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "priority {} is not in 0-7", self.0)
+    }
+}
+
+impl Error for InvalidPriority {}
+
+/// An entry point outside of the loaded image was given to [`VM::set_entry`] in strict mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntryOutsideImage(pub u16);
+
+impl fmt::Display for EntryOutsideImage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "entry x{:04X} is outside of the loaded image", self.0)
+    }
+}
+
+impl Error for EntryOutsideImage {}
+
+/// [`VM::relocate`] was given a delta moving the loaded image below x0000 or past xFFFF
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RelocationOutOfMemory(pub i16);
+
+impl fmt::Display for RelocationOutOfMemory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "moving the image by {} runs it out of memory", self.0)
+    }
+}
+
+impl Error for RelocationOutOfMemory {}
+
+/// Read the next big-endian word of a program, `None` once it is fully read
+fn read_word<P: Read>(program: &mut P) -> Result<Option<u16>, LoadError> {
+    let mut buf = [0; 2];
+    let mut filled = 0;
+    while filled < buf.len() {
+        match program.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(LoadError::Io(e.kind())),
+        }
+    }
+
+    match filled {
+        0 => Ok(None),
+        1 => Err(LoadError::OddLength),
+        _ => Ok(Some(buf[1] as u16 | (buf[0] as u16) << 8)),
+    }
+}
+
+/// A program image read in full but not yet in a VM's memory, see [`parse_image`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoadedImage {
+    pub origin: u16,
+    pub words: Vec<u16>,
+}
+
+/// Read a whole program image without touching any VM, so it can be checked before
+/// [`VM::commit_image`]. Unlike [`VM::load`] nothing is written when the image is invalid
+pub fn parse_image<P: Read>(mut program: P) -> Result<LoadedImage, LoadError> {
+    let origin = read_word(&mut program)?.ok_or(LoadError::EmptyImage)?;
+    let mut words = Vec::new();
+    while let Some(word) = read_word(&mut program)? {
+        words.push(word);
+    }
+    Ok(LoadedImage { origin, words })
+}
+
+/// Split an object file in its origin and words, see [`words_to_obj`] for the reverse
+pub fn obj_to_words(bytes: &[u8]) -> Result<(u16, Vec<u16>), LoadError> {
+    let image = parse_image(bytes)?;
+    Ok((image.origin, image.words))
+}
+
+/// Object file with `words` placed at `origin`: the origin then the words, big-endian
+pub fn words_to_obj(origin: u16, words: &[u16]) -> Vec<u8> {
+    std::iter::once(origin)
+        .chain(words.iter().copied())
+        .flat_map(u16::to_be_bytes)
+        .collect()
+}
+
+/// Outcome of [`VM::step`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepResult {
+    Continue,
+    Halted,
+    /// A TRAP other than HALT was executed, with its vector. The VM can go on like after
+    /// [`StepResult::Continue`]
+    Trap(u8),
+    /// The instruction couldn't complete or hit a watchpoint, the VM stopped
+    Fault(RunResult),
+}
+
+impl StepResult {
+    /// Whether the VM can keep running after this step
+    pub fn is_running(self) -> bool {
+        matches!(self, StepResult::Continue | StepResult::Trap(_))
+    }
+}
+
+/// A step described for debuggers, see [`VM::step_info`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StepInfo {
+    /// Address the instruction was fetched from
+    pub pc: u16,
+    pub raw: u16,
+    /// Disassembly of `raw`
+    pub decoded: String,
+    pub result: StepResult,
+    pub halted: bool,
+}
+
+/// Why a run stopped before the program halted (or that it did)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunResult {
+    Halted,
+    /// Stopped before executing the instruction at `pc`
+    Breakpoint {
+        pc: u16,
+    },
+    /// The subroutine returned to its caller
+    Returned,
+    /// A store targeted a protected address, it was not written
+    ProtectionViolation {
+        addr: u16,
+    },
+    /// The PC reached `addr`, marked as data, it wasn't executed
+    DataExecution {
+        addr: u16,
+    },
+    /// Stopped before executing the instruction at `pc`, its opcode is watched by
+    /// [`VM::break_on_opcode`]
+    OpcodeBreak {
+        pc: u16,
+        opcode: u8,
+    },
+    /// A store wrote the watched address `addr`, the write was done
+    Watchpoint {
+        addr: u16,
+    },
+    /// The interrupt flag was raised, the instruction at `pc` wasn't executed, see
+    /// [`VM::set_interrupt`]
+    Interrupted {
+        pc: u16,
+    },
+    /// With [`VmConfig::strict`], the PC reached `pc`, a zero word outside of the loaded image,
+    /// usually a program missing its HALT. It wasn't executed
+    RanOffEnd {
+        pc: u16,
+    },
+    /// The instruction budget ran out, see [`VM::call_subroutine`]
+    StepLimit,
+    /// The time budget ran out, see [`VM::run_with_timeout`]
+    Timeout,
+    /// The output went past [`VmConfig::max_output_bytes`] with [`OutputOverflow::Halt`]
+    OutputLimitReached,
+    Error(VmError),
+}
+
+/// Declarative setup of a VM, mostly for tests:
+/// `VmBuilder::new().preload([(0x4000, 0x1234)]).build(reader, writer)`
+#[derive(Debug, Clone, Default)]
+pub struct VmBuilder {
+    config: VmConfig,
+    preload: Vec<(u16, u16)>,
+}
+
+impl VmBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn config(mut self, config: VmConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Write these `(address, value)` pairs in memory before anything runs
+    pub fn preload(mut self, cells: impl IntoIterator<Item = (u16, u16)>) -> Self {
+        self.preload.extend(cells);
+        self
+    }
+
+    pub fn build<R: Read, W: Write>(self, reader: R, writer: W) -> VM<R, W> {
+        let mut vm = VM::new(reader, writer);
+        vm.set_config(self.config);
+        for (address, value) in self.preload {
+            vm.memory.write(address, value);
+        }
+        vm
+    }
+}
+
+/// Summary of a [`VM::run_report`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RunReport {
+    pub result: RunResult,
+    /// Executed instructions, the HALT or faulting one included
+    pub instructions: u128,
+    pub r0: u16,
+}
+
+/// Return address given to a subroutine run by [`VM::call_subroutine`], the last address
+/// before the device page
+pub const SUBROUTINE_SENTINEL: u16 = DEVICE_PAGE - 1;
+
+/// Number of instructions between two reads of the clock by [`VM::run_with_timeout`]
+pub const TIMEOUT_CHECK_INTERVAL: u64 = 1000;
+
+/// Number of instructions between two sleeps of a VM paced by [`VM::set_clock_hz`]
+pub const PACE_INTERVAL: u64 = 1000;
+
+/// Handler of the reserved opcode, see [`VM::set_custom_opcode_handler`]
+pub type OpcodeHandler<R, W> = Box<dyn FnMut(&mut VM<R, W>, u16) + Send>;
+
+/// Start of the interrupt vector table, x0100-x01FF
+pub const INTERRUPT_VECTOR_TABLE: u16 = 0x0100;
+
+/// Interrupt raised by [`VM::schedule_interrupt`]
+#[derive(Debug)]
+struct ScheduledInterrupt {
+    at_instruction: u128,
+    vector: u16,
+    priority: u8,
+}
+
+/// Addresses of the loaded image, from `origin` over `len` words wrapping past xFFFF, so an
+/// image ending at xFFFF or wrapping around to x0000 isn't an empty `Range`
+#[derive(Debug, Clone, Copy, Default)]
+struct Extent {
+    origin: u16,
+    len: u32,
+}
+
+impl Extent {
+    fn contains(&self, addr: u16) -> bool {
+        (addr.wrapping_sub(self.origin) as u32) < self.len
+    }
+
+    fn addresses(&self) -> impl Iterator<Item = u16> {
+        let origin = self.origin;
+        (0..self.len).map(move |i| origin.wrapping_add(i as u16))
+    }
+}
+
+/// Instruction rate set by [`VM::set_clock_hz`]
+#[derive(Debug)]
+struct Clock {
+    hz: u32,
+    start: Instant,
+    executed: u64,
+}
+
+/// What an address holds, see [`VM::classify_address`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddrKind {
+    Code,
+    Data,
+    Unknown,
+}
+
+/// Kind of a region of [`VM::memory_map`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionKind {
+    /// Instructions of the loaded image, see [`VM::classify_address`]
+    Code,
+    /// Data of the loaded image
+    Data,
+    /// Outside of the image but not zero, written by the program or the host
+    Written,
+    /// Outside of the image and zero
+    Untouched,
+    /// The memory mapped devices
+    Device,
+}
+
+/// A load or store done by an instruction, see [`VM::record_mem_accesses`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemAccess {
+    /// Address of the instruction doing the access
+    pub pc: u16,
+    pub addr: u16,
+    pub kind: AccessKind,
+    pub value: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+/// Diagnostic reported to the trace hook, see [`VM::set_trace_hook`]. It never stops the VM
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceEvent {
+    /// The instruction at `pc` moved the stack pointer R6 to `sp`, outside the range given to
+    /// [`VM::set_stack_range`]
+    StackOutOfRange { pc: u16, sp: u16 },
+}
+
+/// Condition code register as "N", "Z" or "P", "?" for anything else than a single flag
+fn cc_str(cond: u16) -> &'static str {
+    match cond {
+        0b100 => "N",
+        0b010 => "Z",
+        0b001 => "P",
+        _ => "?",
+    }
+}
+
+/// Million instructions per second for `count` instructions executed in `duration`
+pub fn mips(count: u128, duration: Duration) -> f64 {
+    count as f64 / duration.as_secs_f64() / 1_000_000.0
+}
+
+/// Index of the first `(pc, instruction)` differing between two traces, or where the
+/// shorter one ends. `None` when they are identical
+pub fn diff_traces(a: &[(u16, u16)], b: &[(u16, u16)]) -> Option<usize> {
+    if a == b {
+        return None;
+    }
+    Some(
+        a.iter()
+            .zip(b)
+            .position(|(x, y)| x != y)
+            .unwrap_or(a.len().min(b.len())),
+    )
+}
+
+/// First divergence of two traces with the entries around it, see [`diff_traces_context`]
+#[derive(Debug, PartialEq, Eq)]
+pub struct TraceDivergence {
+    pub index: usize,
+    /// Entries of the first trace from `context` before `index` to `context` after it
+    pub a: Vec<(u16, u16)>,
+    /// Entries of the second trace over the same span
+    pub b: Vec<(u16, u16)>,
+}
+
+/// Like [`diff_traces`] but also return the `context` entries on each side of the divergence
+pub fn diff_traces_context(
+    a: &[(u16, u16)],
+    b: &[(u16, u16)],
+    context: usize,
+) -> Option<TraceDivergence> {
+    let index = diff_traces(a, b)?;
+    let around = |trace: &[(u16, u16)]| {
+        let start = index.saturating_sub(context).min(trace.len());
+        let end = (index + context + 1).min(trace.len());
+        trace[start..end].to_vec()
+    };
+    Some(TraceDivergence {
+        index,
+        a: around(a),
+        b: around(b),
+    })
+}
+
+/// An LC-3 machine reading its input from `R` and writing its output to `W`.
+///
+/// The VM is `Send` whenever `R` and `W` are, so it can be moved to a worker thread.
 pub struct VM<R, W>
 where
     R: Read,
     W: Write,
 {
     memory: Memory,
-    registers: HashMap<Reg, u16>,
+    /// Indexed by [`Reg`], see [`VM::reg`]
+    registers: [u16; REG_COUNT],
     halt: bool,
+    image: Extent,
+    config: VmConfig,
+    breakpoints: HashSet<u16>,
+    watchpoints: HashSet<u16>,
+    opcode_breaks: HashSet<u8>,
+    data: HashSet<u16>,
+    labels: HashMap<u16, String>,
+    /// Source line of each assembled instruction, for [`VM::coverage_report`]
+    source_lines: HashMap<u16, usize>,
+    call_stack: Vec<u16>,
+    fault: Option<RunResult>,
+    /// Processor status bits other than the condition codes, kept in [`Reg::RCond`]
+    psr: u16,
+    opcode_histogram: [u64; 16],
+    trap_histogram: HashMap<u8, u64>,
+    address_counts: HashMap<u16, u64>,
+    patches: HashMap<u16, u16>,
+    /// Addresses of multiply loops run at once, see [`VM::accelerate_multiply`]
+    multiply_loops: HashSet<u16>,
+    scheduled_interrupts: Vec<ScheduledInterrupt>,
+    recorded_input: Option<Vec<u8>>,
+    custom_opcode: Option<OpcodeHandler<R, W>>,
+    output_sink: Option<Box<dyn FnMut(u8) + Send>>,
+    trace_hook: Option<Box<dyn FnMut(TraceEvent) + Send>>,
+    interrupt: Option<Arc<AtomicBool>>,
+    clock: Option<Clock>,
+    /// Bytes written by the traps, checked against [`VmConfig::max_output_bytes`]
+    output_bytes: usize,
+    stack_range: Option<Range<u16>>,
+    /// Cells the trap entry and RTI may push to and pop from, see [`VM::set_supervisor_stack`]
+    supervisor_stack: Option<Range<u16>>,
+    /// Address of the instruction being executed
+    current_pc: u16,
+    mem_accesses: Option<Vec<MemAccess>>,
     reader: R,
     writer: W,
 }
@@ -45,246 +549,3643 @@ where
     R: Read,
     W: Write,
 {
-    pub fn load<P>(&mut self, mut program: P)
+    /// A reset VM reading from `reader` and writing to `writer`: memory and registers at
+    /// their reset values, the PC on [`PC_START`]
+    pub fn new(reader: R, writer: W) -> Self {
+        Self {
+            memory: Memory::default(),
+            registers: initial_registers(),
+            halt: false,
+            image: Extent::default(),
+            config: VmConfig::default(),
+            breakpoints: HashSet::new(),
+            watchpoints: HashSet::new(),
+            opcode_breaks: HashSet::new(),
+            data: HashSet::new(),
+            labels: HashMap::new(),
+            source_lines: HashMap::new(),
+            call_stack: Vec::new(),
+            fault: None,
+            psr: 0,
+            opcode_histogram: [0; 16],
+            trap_histogram: HashMap::new(),
+            address_counts: HashMap::new(),
+            patches: HashMap::new(),
+            multiply_loops: HashSet::new(),
+            scheduled_interrupts: Vec::new(),
+            recorded_input: None,
+            custom_opcode: None,
+            output_sink: None,
+            trace_hook: None,
+            interrupt: None,
+            clock: None,
+            output_bytes: 0,
+            stack_range: None,
+            supervisor_stack: None,
+            current_pc: PC_START as u16,
+            mem_accesses: None,
+            reader,
+            writer,
+        }
+    }
+
+    /// Load an object file, point the PC at its origin and return it. An image running past
+    /// xFFFF wraps around to x0000, unless [`VmConfig::strict`] is set. The words read before
+    /// an error stay in memory
+    pub fn load<P>(&mut self, mut program: P) -> Result<u16, LoadError>
     where
         P: Read,
     {
-        let mut buf = [0; 2];
-        let mut read_result = program.read_exact(&mut buf);
+        let Some(mut base_address) = read_word(&mut program)? else {
+            return Err(LoadError::EmptyImage);
+        };
+        self.set_reg(Reg::RPC, base_address);
+        let start = base_address;
 
-        let mut base_address = buf[1] as u16 | (buf[0] as u16) << 8;
-        self.registers.insert(Reg::RPC, base_address);
-
-        while read_result.is_ok() {
-            read_result = program.read_exact(&mut buf);
-
-            let instruction = buf[1] as u16 | (buf[0] as u16) << 8;
+        let mut len: u32 = 0;
+        while let Some(instruction) = read_word(&mut program)? {
+            self.check_image_fits(start, len + 1)?;
             self.memory.write(base_address, instruction);
-            base_address += 1;
+            base_address = base_address.wrapping_add(1);
+            len += 1;
         }
+        self.image = Extent { origin: start, len };
+        Ok(start)
     }
 
-    pub fn run(&mut self) -> u128 {
-        let mut i_count: u128 = 0;
+    /// Load a program whose origin must be `expected`, nothing is written otherwise
+    pub fn load_expecting_origin<P: Read>(
+        &mut self,
+        program: P,
+        expected: u16,
+    ) -> Result<(), LoadError> {
+        let image = parse_image(program)?;
+        if image.origin != expected {
+            return Err(LoadError::OriginMismatch {
+                found: image.origin,
+                expected,
+            });
+        }
+        self.commit_image(&image)
+    }
+
+    /// Start the execution at `addr` rather than at the origin, call it after loading.
+    /// With [`VmConfig::strict`] the entry must be in the loaded image
+    pub fn set_entry(&mut self, addr: u16) -> Result<(), EntryOutsideImage> {
+        if self.config.strict && !self.image.contains(addr) {
+            return Err(EntryOutsideImage(addr));
+        }
+        self.set_reg(Reg::RPC, addr);
+        Ok(())
+    }
 
-        while !self.halt {
-            let current_addr = self.registers[&Reg::RPC];
-            let instruction = self.memory.read(current_addr);
+    /// Write a parsed image at its origin and point the PC at it, see [`VM::load_words`]
+    pub fn commit_image(&mut self, image: &LoadedImage) -> Result<(), LoadError> {
+        self.load_words(image.origin, &image.words)
+    }
 
-            self.inc_rpc();
+    /// Write `words` from `origin` and point the PC at it, e.g. with
+    /// [`assembler::Assembled::words`], without going through an object file. Like
+    /// [`VM::load`] the words wrap around past xFFFF unless [`VmConfig::strict`] is set, nothing
+    /// is written then
+    pub fn load_words(&mut self, origin: u16, words: &[u16]) -> Result<(), LoadError> {
+        self.check_image_fits(origin, words.len() as u32)?;
+        self.set_reg(Reg::RPC, origin);
+        let mut address = origin;
+        for word in words {
+            self.memory.write(address, *word);
+            address = address.wrapping_add(1);
+        }
+        self.image = Extent {
+            origin,
+            len: words.len() as u32,
+        };
+        Ok(())
+    }
 
-            let op: Box<dyn Instruction<R, W>> = instruction.into();
+    /// With [`VmConfig::strict`], an image of `len` words at `origin` must end by xFFFF
+    fn check_image_fits(&self, origin: u16, len: u32) -> Result<(), LoadError> {
+        if self.config.strict && origin as u32 + len > 0x10000 {
+            return Err(LoadError::ImageTooLarge { origin });
+        }
+        Ok(())
+    }
 
-            // println!("State: {:#?}", self.registers);
-            // print!("({i_count}) Instruction {current_addr:04x}: {instruction:016b}/{instruction:04x}.");
-            // println!(" Decoded as {op:?}");
+    /// Load an assembled program, keeping its labels, data addresses and source lines for
+    /// [`VM::write_listing`], [`VmConfig::strict_execution`] and [`VM::coverage_report`]
+    pub fn load_assembled(&mut self, assembled: &assembler::Assembled) -> Result<(), LoadError> {
+        self.load(&assembled.to_bytes()[..])?;
+        self.mark_data(assembled.data.iter().copied());
+        self.source_lines.extend(&assembled.lines);
+        self.labels.extend(
+            assembled
+                .symbols
+                .iter()
+                .map(|(label, address)| (*address, label.clone())),
+        );
+        Ok(())
+    }
 
-            op.execute(self);
-            i_count += 1;
+    /// Move the loaded image by `delta` words and shift RPC along with it, the image must
+    /// stay within x0000-xFFFF.
+    ///
+    /// This is a raw memory move: instructions are copied as they are, so PC-relative
+    /// references inside the image keep working but absolute addresses (e.g. a `.FILL`
+    /// holding a label) are not fixed up. The vacated cells are zeroed. The labels, data
+    /// addresses, source lines and breakpoints inside the image move with it
+    pub fn relocate(&mut self, delta: i16) -> Result<(), RelocationOutOfMemory> {
+        let start = self.image.origin as i32 + delta as i32;
+        if start < 0 || start + self.image.len as i32 > 0x10000 {
+            return Err(RelocationOutOfMemory(delta));
+        }
+        let image = self.image;
+        let shift = |addr: u16| {
+            if image.contains(addr) {
+                addr.wrapping_add_signed(delta)
+            } else {
+                addr
+            }
+        };
 
-            // if i_count % 100_000_000 == 0 {
-            //     println!("{i_count} instructions executed.");
-            // }
+        let words: Vec<u16> = image.addresses().map(|a| self.memory.read(a)).collect();
+        for address in image.addresses() {
+            self.memory.write(address, 0);
+        }
+        for (i, word) in words.into_iter().enumerate() {
+            self.memory.write(start as u16 + i as u16, word);
         }
-        i_count
+
+        self.labels = self.labels.drain().map(|(a, l)| (shift(a), l)).collect();
+        self.data = self.data.drain().map(shift).collect();
+        self.source_lines = self
+            .source_lines
+            .drain()
+            .map(|(a, l)| (shift(a), l))
+            .collect();
+        self.breakpoints = self.breakpoints.drain().map(shift).collect();
+        self.image = Extent {
+            origin: start as u16,
+            len: image.len,
+        };
+        let rpc = self.get_rpc().wrapping_add_signed(delta);
+        self.set_reg(Reg::RPC, rpc);
+        Ok(())
     }
 
-    fn inc_rpc(&mut self) -> u16 {
-        let next_addr = self.registers[&Reg::RPC] + 1;
-        self.registers.insert(Reg::RPC, next_addr);
-        next_addr
+    /// Run until the VM halts or faults and return the number of executed instructions,
+    /// see [`VM::resume`] to know why it stopped.
+    pub fn run(&mut self) -> u128 {
+        self.run_report().instructions
     }
 
-    fn get_rpc(&self) -> u16 {
-        self.registers[&Reg::RPC]
+    /// Like [`VM::run`] but also tell why the VM stopped and the final R0, the usual result
+    /// register, for harnesses running many programs
+    pub fn run_report(&mut self) -> RunReport {
+        let start = self.instruction_count();
+
+        let result = loop {
+            match self.step() {
+                StepResult::Continue | StepResult::Trap(_) => {}
+                StepResult::Halted => break RunResult::Halted,
+                StepResult::Fault(fault) => break fault,
+            }
+        };
+        RunReport {
+            result,
+            instructions: self.instruction_count() - start,
+            r0: self.reg(Reg::R0),
+        }
     }
-    fn set_nzp(&mut self, r: &Reg) {
-        if self.registers[r] == 0 {
-            self.registers.insert(Reg::RCond, 1 << 1);
-        } else if self.registers[r] >> 15 == 1 {
-            self.registers.insert(Reg::RCond, 1 << 2);
+
+    /// Run like [`VM::run`] but stop with [`RunResult::Timeout`] once `dur` has elapsed. The
+    /// clock is read every [`TIMEOUT_CHECK_INTERVAL`] instructions
+    pub fn run_with_timeout(&mut self, dur: Duration) -> RunResult {
+        let start = Instant::now();
+        let mut i_count: u64 = 0;
+        loop {
+            match self.step() {
+                StepResult::Continue | StepResult::Trap(_) => {}
+                StepResult::Halted => return RunResult::Halted,
+                StepResult::Fault(fault) => return fault,
+            }
+            i_count += 1;
+            if i_count.is_multiple_of(TIMEOUT_CHECK_INTERVAL) && start.elapsed() >= dur {
+                return RunResult::Timeout;
+            }
+        }
+    }
+
+    /// Fetch, decode and execute the instruction at RPC
+    pub fn step(&mut self) -> StepResult {
+        if self.halt {
+            return StepResult::Halted;
+        }
+
+        self.enter_scheduled_interrupt();
+        if let Some(fault) = self.fault.take() {
+            return StepResult::Fault(fault);
+        }
+
+        let current_addr = self.reg(Reg::RPC);
+        if let Some(interrupt) = &self.interrupt {
+            if interrupt.swap(false, Ordering::Relaxed) {
+                return StepResult::Fault(RunResult::Interrupted { pc: current_addr });
+            }
+        }
+        if self.config.strict_execution && self.data.contains(&current_addr) {
+            return StepResult::Fault(RunResult::DataExecution { addr: current_addr });
+        }
+        if self.config.strict
+            && !self.image.contains(current_addr)
+            && self.memory.read(current_addr) == 0
+        {
+            return StepResult::Fault(RunResult::RanOffEnd { pc: current_addr });
+        }
+        if self.multiply_loops.contains(&current_addr) && self.run_multiply_loop(current_addr) {
+            return StepResult::Continue;
+        }
+        let instruction = self.bus_read(current_addr);
+        self.current_pc = current_addr;
+
+        self.inc_rpc();
+        self.memory.timer.ticks += 1;
+
+        if self.config.profile_addresses {
+            *self.address_counts.entry(current_addr).or_default() += 1;
+        }
+
+        let opcode = instruction >> 12;
+        self.opcode_histogram[opcode as usize] += 1;
+        if opcode == 0b1111 {
+            *self.trap_histogram.entry(instruction as u8).or_default() += 1;
+        }
+
+        let op = Op::decode(instruction);
+
+        // println!("State: {:#?}", self.registers);
+        // print!("Instruction {current_addr:04x}: {instruction:016b}/{instruction:04x}.");
+        // println!(" Decoded as {op:?}");
+
+        let sp = self.reg(Reg::R6);
+        op.execute(self);
+        self.check_stack(current_addr, sp);
+        self.pace();
+
+        if let Some(fault) = self.fault.take() {
+            StepResult::Fault(fault)
+        } else if self.halt {
+            StepResult::Halted
+        } else if opcode == 0b1111 {
+            StepResult::Trap(instruction as u8)
         } else {
-            self.registers.insert(Reg::RCond, 1 << 0);
+            StepResult::Continue
         }
     }
-}
 
-impl Default for VM<LibCReader, Stdout> {
-    fn default() -> Self {
-        let input = LibCReader;
-        let output = io::stdout();
-        Self {
-            memory: Memory::default(),
-            registers: HashMap::from([
-                (Reg::R0, 0),
-                (Reg::R1, 0),
-                (Reg::R2, 0),
-                (Reg::R3, 0),
-                (Reg::R4, 0),
-                (Reg::R5, 0),
-                (Reg::R6, 0),
-                (Reg::R7, 0),
-                (Reg::RCond, 1 << 1),
-                (Reg::RPC, PC_START as u16),
-            ]),
-            halt: false,
-            reader: input,
-            writer: output,
+    /// Step as a debugger does: like [`VM::step`], except that a TRAP going through the trap
+    /// vector table is stepped over unless [`VmConfig::step_into_traps`] is set. Stepping over
+    /// runs the routine up to a temporary breakpoint on the return address, it stops earlier on
+    /// the user breakpoints with [`RunResult::Breakpoint`]
+    pub fn debug_step(&mut self) -> StepResult {
+        let pc = self.get_rpc();
+        let word = self.current_word();
+        let native = !matches!(
+            disassembler::variant_of(word),
+            disassembler::Variant::TrapVector(_)
+        );
+        if self.config.step_into_traps || native || self.config.compat_reference {
+            return self.step();
+        }
+
+        let ret = pc.wrapping_add(1);
+        let temporary = self.breakpoints.insert(ret);
+        let result = self.resume();
+        if temporary {
+            self.breakpoints.remove(&ret);
+        }
+        match result {
+            RunResult::Halted => StepResult::Halted,
+            RunResult::Breakpoint { pc } if pc == ret && temporary => StepResult::Trap(word as u8),
+            stop => StepResult::Fault(stop),
         }
     }
-}
 
-impl Default for VM<&[u8], Vec<u8>> {
-    fn default() -> Self {
-        Self {
-            memory: Memory::default(),
-            registers: HashMap::from([
-                (Reg::R0, 0),
-                (Reg::R1, 0),
-                (Reg::R2, 0),
-                (Reg::R3, 0),
-                (Reg::R4, 0),
-                (Reg::R5, 0),
-                (Reg::R6, 0),
-                (Reg::R7, 0),
-                (Reg::RCond, 1 << 1),
-                (Reg::RPC, PC_START as u16),
-            ]),
-            halt: false,
-            reader: b"",
-            writer: Vec::default(),
+    /// [`VM::step`] along with the instruction that was executed, without decoding it twice
+    /// on the caller side
+    pub fn step_info(&mut self) -> StepInfo {
+        let pc = self.get_rpc();
+        let raw = self.memory.read(pc);
+        let result = self.step();
+        StepInfo {
+            pc,
+            raw,
+            decoded: disassemble(raw),
+            result,
+            halted: self.halt,
         }
     }
-}
 
-struct Memory {
-    mem: [u16; u16::MAX as usize + 1],
-}
+    /// Run until the VM halts, faults or reaches a breakpoint.
+    ///
+    /// A breakpoint on the current instruction is ignored so a stopped run can be resumed.
+    pub fn resume(&mut self) -> RunResult {
+        loop {
+            match self.step() {
+                StepResult::Continue | StepResult::Trap(_) => {}
+                StepResult::Halted => return RunResult::Halted,
+                StepResult::Fault(fault) => return fault,
+            }
+            if let Some(stop) = self.break_at_pc() {
+                return stop;
+            }
+        }
+    }
 
-fn get_key() -> Option<u16> {
-    match unsafe_zone::get_char() {
-        0 => None,
-        c => Some(c as u16),
+    /// Breakpoint or opcode break on the instruction about to be executed
+    fn break_at_pc(&self) -> Option<RunResult> {
+        let pc = self.get_rpc();
+        let opcode = (self.memory.read(pc) >> 12) as u8;
+        if self.breakpoints.contains(&pc) {
+            Some(RunResult::Breakpoint { pc })
+        } else if self.opcode_breaks.contains(&opcode) {
+            Some(RunResult::OpcodeBreak { pc, opcode })
+        } else {
+            None
+        }
     }
-}
 
-impl Memory {
-    fn read(&mut self, address: u16) -> u16 {
-        if address == MR_KBSR {
-            let key = get_key();
-            match key {
-                Some(c) => {
-                    self.write(MR_KBSR, 1 << 15);
-                    self.write(MR_KBDR, c);
-                }
-                None => self.write(MR_KBSR, 0x0),
+    /// Run until the current subroutine returns to its caller, a breakpoint is reached or the VM halts.
+    ///
+    /// The call depth is tracked with a shadow stack pushed by JSR/JSRR and popped by RET.
+    pub fn step_out(&mut self) -> RunResult {
+        let depth = self.call_stack.len();
+        loop {
+            match self.step() {
+                StepResult::Continue | StepResult::Trap(_) => {}
+                StepResult::Halted => return RunResult::Halted,
+                StepResult::Fault(fault) => return fault,
+            }
+            if self.call_stack.len() < depth {
+                return RunResult::Returned;
+            }
+            if let Some(stop) = self.break_at_pc() {
+                return stop;
             }
         }
-        self.mem[address as usize]
     }
 
-    fn write(&mut self, address: u16, val: u16) -> () {
-        self.mem[address as usize] = val;
+    /// Run the subroutine at `entry` alone, e.g. to test a library routine: R7 is set to
+    /// [`SUBROUTINE_SENTINEL`] and the VM runs until the routine returns there
+    /// ([`RunResult::Returned`]), halts, faults or executes `max_steps` instructions
+    /// ([`RunResult::StepLimit`]). Breakpoints are ignored
+    pub fn call_subroutine(&mut self, entry: u16, max_steps: u128) -> RunResult {
+        self.set_reg(Reg::R7, SUBROUTINE_SENTINEL);
+        self.set_reg(Reg::RPC, entry);
+        for _ in 0..max_steps {
+            match self.step() {
+                StepResult::Continue | StepResult::Trap(_) => {}
+                StepResult::Halted => return RunResult::Halted,
+                StepResult::Fault(fault) => return fault,
+            }
+            if self.get_rpc() == SUBROUTINE_SENTINEL {
+                return RunResult::Returned;
+            }
+        }
+        RunResult::StepLimit
     }
-}
 
-impl Default for Memory {
-    fn default() -> Self {
-        Self {
-            mem: [0; u16::MAX as usize + 1],
+    /// Step up to `n` instructions and return the address and disassembly of each executed one.
+    /// A step stopping before its instruction, e.g. on [`RunResult::RanOffEnd`] or once halted,
+    /// adds nothing
+    pub fn trace_n(&mut self, n: u32) -> Vec<(u16, String)> {
+        let mut trace = Vec::new();
+        for _ in 0..n {
+            let executed = self.instruction_count();
+            let result = self.step();
+            if self.instruction_count() != executed {
+                let pc = self.current_pc;
+                trace.push((pc, disassemble(self.memory.read(pc))));
+            }
+            if !result.is_running() {
+                break;
+            }
         }
+        trace
     }
-}
 
-#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
-enum Reg {
-    R0,
-    R1,
-    R2,
-    R3,
-    R4,
-    R5,
-    R6,
-    R7,
-    RPC,
-    RCond,
-}
+    /// Iterate over the program one instruction at a time, see [`Stepper`]
+    pub fn stepper(&mut self) -> Stepper<'_, R, W> {
+        Stepper {
+            vm: self,
+            done: false,
+        }
+    }
 
-impl Reg {
-    fn dr(instruction: u16) -> Self {
-        let reg_nb = (instruction >> 9) & 0b0000000000000111;
-        reg_nb.into()
+    /// Addresses in the loaded image referring to `target`: instructions reaching it through
+    /// their PC-relative offset and data words (see [`VM::mark_data`]) holding it
+    pub fn find_references(&self, target_address: u16) -> Vec<u16> {
+        self.image
+            .addresses()
+            .filter(|address| {
+                let word = self.memory.read(*address);
+                if self.data.contains(address) {
+                    word == target_address
+                } else {
+                    target(*address, word) == Some(target_address)
+                }
+            })
+            .collect()
     }
-    fn sr1(instruction: u16) -> Self {
-        let reg_nb = (instruction >> 6) & 0b0000000000000111;
-        reg_nb.into()
+
+    /// Start or stop recording the loads and stores of the instructions, instruction fetches
+    /// aren't recorded. Stopping drops what was recorded
+    pub fn record_mem_accesses(&mut self, enabled: bool) {
+        self.mem_accesses = enabled.then(Vec::new);
     }
-    fn sr2(instruction: u16) -> Self {
-        let reg_nb = instruction & 0b0000000000000111;
-        reg_nb.into()
+
+    /// The accesses recorded since the last call
+    pub fn take_mem_accesses(&mut self) -> Vec<MemAccess> {
+        self.mem_accesses
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
     }
-}
 
-impl From<u16> for Reg {
-    fn from(value: u16) -> Self {
-        match value {
-            0 => Self::R0,
-            1 => Self::R1,
-            2 => Self::R2,
-            3 => Self::R3,
-            4 => Self::R4,
-            5 => Self::R5,
-            6 => Self::R6,
-            7 => Self::R7,
-            _ => panic!("The number {value} is not in [0..7]"),
-        }
+    /// Slow the VM down to about `hz` instructions per second, 0 runs at full speed.
+    ///
+    /// The VM sleeps every [`PACE_INTERVAL`] instructions to catch up with the clock, so the
+    /// rate is only approximated over short runs.
+    pub fn set_clock_hz(&mut self, hz: u32) {
+        self.clock = (hz > 0).then(|| Clock {
+            hz,
+            start: Instant::now(),
+            executed: 0,
+        });
     }
-}
 
-#[cfg(test)]
-mod tests {
+    /// Stop running before the next instruction when `flag` is raised, e.g. from a SIGINT
+    /// handler. The flag is lowered when the VM stops so running again resumes the program
+    pub fn set_interrupt(&mut self, flag: Arc<AtomicBool>) {
+        self.interrupt = Some(flag);
+    }
 
-    use std::io::BufReader;
+    /// Execute the words with the reserved opcode 1101 with `handler`, given the VM and the
+    /// word, to prototype a new instruction. The PC already points to the next instruction.
+    /// Without handler such a word stops the VM with [`VmError::IllegalOpcode`]
+    pub fn set_custom_opcode_handler(&mut self, handler: OpcodeHandler<R, W>) {
+        self.custom_opcode = Some(handler);
+    }
 
-    use super::*;
+    /// Call `hook` with the diagnostics found while running, see [`TraceEvent`]
+    pub fn set_trace_hook(&mut self, hook: Box<dyn FnMut(TraceEvent) + Send>) {
+        self.trace_hook = Some(hook);
+    }
+
+    /// Watch R6, the stack pointer by convention, and report a [`TraceEvent::StackOutOfRange`]
+    /// each time an instruction moves it outside `range`
+    pub fn set_stack_range(&mut self, range: Range<u16>) {
+        self.stack_range = Some(range);
+    }
+
+    /// Bound the stack used by the trap entry and RTI to the cells of `range`: the stack is
+    /// empty when R6 is `range.end` and full when it is `range.start`. Pushing on a full
+    /// stack or popping an empty one stops the VM with [`VmError::StackFault`], the faulting
+    /// push or pop leaves R6 and the memory untouched
+    pub fn set_supervisor_stack(&mut self, range: Range<u16>) {
+        self.supervisor_stack = Some(range);
+    }
+
+    /// Call `sink` with each output byte as soon as a trap produces it, whatever the buffering
+    /// of the writer, which still receives the output
+    pub fn set_output_sink(&mut self, sink: Box<dyn FnMut(u8) + Send>) {
+        self.output_sink = Some(sink);
+    }
+
+    /// Write an objdump-like listing of the loaded image: address, word, label, disassembly,
+    /// target of PC-relative instructions and, with [`VmConfig::profile_addresses`], how many
+    /// times the address ran
+    pub fn write_listing<Wt: Write>(&self, mut out: Wt) -> io::Result<()> {
+        for address in self.image.addresses() {
+            let word = self.memory.read(address);
+            let label = self.labels.get(&address).map_or("", String::as_str);
+            let text = if self.data.contains(&address) {
+                format!(".FILL x{word:04X}")
+            } else {
+                disassemble(word)
+            };
+            write!(out, "x{address:04X}  x{word:04X}  {label:<8} {text}")?;
+
+            if let Some(to) = target(address, word).filter(|_| !self.data.contains(&address)) {
+                write!(out, "  ; -> x{to:04X}")?;
+                if let Some(label) = self.labels.get(&to) {
+                    write!(out, " {label}")?;
+                }
+            }
+            if let Some(count) = self.address_counts.get(&address) {
+                write!(out, "  [{count}]")?;
+            }
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+
+    /// Replace the instruction at `addr` with `word`, bypassing the devices, and return its
+    /// disassembly. The address is no longer taken as data and its execution count restarts.
+    /// The first word replaced at each address is kept in [`VM::patches`]
+    pub fn patch(&mut self, addr: u16, word: u16) -> String {
+        let original = self.memory.read(addr);
+        self.patches.entry(addr).or_insert(original);
+        self.memory.mem[addr as usize] = word;
+        self.data.remove(&addr);
+        self.address_counts.remove(&addr);
+        disassemble(word)
+    }
+
+    /// Run the multiply loop at `addr` in one go when reached, with the same outcome as
+    /// interpreting it: registers, condition codes, instruction count and histograms. The loop
+    /// must be exactly
+    ///
+    /// ```text
+    /// addr    ADD Racc, Racc, Rm
+    ///         ADD Rc, Rc, #-1
+    ///         BRp addr
+    /// ```
+    ///
+    /// with three distinct registers and a positive count in Rc, otherwise it is interpreted.
+    /// Breakpoints and the clock set by [`VM::set_clock_hz`] don't see the skipped iterations
+    pub fn accelerate_multiply(&mut self, addr: u16) {
+        self.multiply_loops.insert(addr);
+    }
+
+    /// Start or stop keeping every byte the program reads (GETC, IN, the numeric input traps
+    /// and the keyboard device), see [`VM::replay`]. Stopping drops what was recorded
+    pub fn record_input(&mut self, enabled: bool) {
+        self.recorded_input = enabled.then(Vec::new);
+    }
+
+    /// The bytes read since the recording started
+    pub fn recorded_input(&self) -> &[u8] {
+        self.recorded_input.as_deref().unwrap_or_default()
+    }
+
+    /// Original words of the addresses changed by [`VM::patch`]
+    pub fn patches(&self) -> &HashMap<u16, u16> {
+        &self.patches
+    }
+
+    /// Stop before executing the instruction at `address`
+    pub fn add_breakpoint(&mut self, address: u16) {
+        self.breakpoints.insert(address);
+    }
+
+    pub fn remove_breakpoint(&mut self, address: u16) {
+        self.breakpoints.remove(&address);
+    }
+
+    pub fn breakpoints(&self) -> &HashSet<u16> {
+        &self.breakpoints
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    /// Record addresses holding data rather than code, like [`assembler::Assembled::data`],
+    /// for [`VmConfig::strict_execution`]
+    pub fn mark_data(&mut self, addresses: impl IntoIterator<Item = u16>) {
+        self.data.extend(addresses);
+    }
+
+    /// Whether `addr` holds code or data. Addresses of the loaded image are code unless marked
+    /// as data when the VM knows the labels or data of the program, e.g. after
+    /// [`VM::load_assembled`]. Otherwise a word of the image is code when it decodes to a
+    /// valid instruction. Addresses outside of the image are unknown
+    pub fn classify_address(&self, addr: u16) -> AddrKind {
+        if self.data.contains(&addr) {
+            AddrKind::Data
+        } else if !self.image.contains(addr) {
+            AddrKind::Unknown
+        } else if !self.labels.is_empty() || !self.data.is_empty() {
+            AddrKind::Code
+        } else if disassemble(self.memory.read(addr)).starts_with(".FILL") {
+            AddrKind::Data
+        } else {
+            AddrKind::Code
+        }
+    }
+
+    /// Overview of the memory as contiguous regions of the same kind, from x0000 to xFFFF.
+    /// The last region is the device page, from xFE00 up to and including xFFFF
+    pub fn memory_map(&self) -> Vec<(RangeInclusive<u16>, RegionKind)> {
+        let mut map: Vec<(RangeInclusive<u16>, RegionKind)> = Vec::new();
+        for addr in 0..DEVICE_PAGE {
+            let kind = match self.classify_address(addr) {
+                AddrKind::Code => RegionKind::Code,
+                AddrKind::Data => RegionKind::Data,
+                AddrKind::Unknown if self.memory.read(addr) != 0 => RegionKind::Written,
+                AddrKind::Unknown => RegionKind::Untouched,
+            };
+            match map.last_mut() {
+                Some((range, last)) if *last == kind => *range = *range.start()..=addr,
+                _ => map.push((addr..=addr, kind)),
+            }
+        }
+        map.push((DEVICE_PAGE..=u16::MAX, RegionKind::Device));
+        map
+    }
+
+    /// Stop before any instruction with this opcode (bits [15:12]), e.g. 0b1111 for every TRAP
+    pub fn break_on_opcode(&mut self, opcode: u8) {
+        self.opcode_breaks.insert(opcode);
+    }
+
+    pub fn remove_opcode_break(&mut self, opcode: u8) {
+        self.opcode_breaks.remove(&opcode);
+    }
+
+    /// Stop right after an instruction stores to `address`
+    pub fn add_watchpoint(&mut self, address: u16) {
+        self.watchpoints.insert(address);
+    }
+
+    pub fn remove_watchpoint(&mut self, address: u16) {
+        self.watchpoints.remove(&address);
+    }
+
+    pub fn watchpoints(&self) -> &HashSet<u16> {
+        &self.watchpoints
+    }
+
+    pub fn clear_watchpoints(&mut self) {
+        self.watchpoints.clear();
+    }
+
+    /// Replace the input, e.g. to feed a new script to a running session
+    pub fn set_reader(&mut self, reader: R) {
+        self.reader = reader;
+    }
+
+    /// Replace the output, the previous writer is returned so what it buffered can be drained
+    pub fn set_writer(&mut self, writer: W) -> W {
+        std::mem::replace(&mut self.writer, writer)
+    }
+
+    /// Format the registers as a single line, e.g. `R0=x0000 ... R7=x0000 PC=x3000 COND=x0002`,
+    /// going up to R15 with the `extended-registers` feature
+    pub fn dump_registers(&self) -> String {
+        let mut dump = String::new();
+        for i in 0..GPR_COUNT {
+            let reg = Reg::from(i);
+            dump.push_str(&format!("R{i}=x{:04X} ", self.reg(reg)));
+        }
+        dump.push_str(&format!(
+            "PC=x{:04X} COND=x{:04X}",
+            self.reg(Reg::RPC),
+            self.reg(Reg::RCond)
+        ));
+        dump
+    }
+
+    /// Condition code set by the last instruction writing a register: "N", "Z" or "P", "?" when
+    /// the register holds anything else than a single flag
+    pub fn cc_string(&self) -> &'static str {
+        cc_str(self.reg(Reg::RCond))
+    }
+
+    /// Word at the PC, the next instruction to execute, read without the device side effects
+    pub fn current_word(&self) -> u16 {
+        self.memory.read(self.get_rpc())
+    }
+
+    /// Whether a HALT was executed since the VM was created or [`VM::clear_halt`] was last called
+    pub fn is_halted(&self) -> bool {
+        self.halt
+    }
+
+    /// Let the VM run again after a HALT, also setting the MCR clock enable bit back.
+    ///
+    /// The PC is left pointing after the HALT instruction, so a plain `run()` resumes
+    /// with the instruction following it.
+    pub fn clear_halt(&mut self) {
+        self.halt = false;
+        self.memory.mem[MR_MCR as usize] |= 1 << 15;
+    }
+
+    /// Read `addr` as a load instruction would, through the memory mapped devices: reading the
+    /// keyboard status (xFE00) polls the reader, which may consume a key into the data register.
+    /// A bus or reader error is returned rather than stopping the next [`VM::step`]
+    pub fn read_memory(&mut self, addr: u16) -> Result<u16, VmError> {
+        let pending = self.fault.take();
+        let value = self.bus_read(addr);
+        match std::mem::replace(&mut self.fault, pending) {
+            Some(RunResult::Error(e)) => Err(e),
+            _ => Ok(value),
+        }
+    }
+
+    /// Store `val` at `addr`, without the checks of [`VmConfig::protect_zero_page`] nor the
+    /// watchpoints, e.g. to set up data before running
+    pub fn write_memory(&mut self, addr: u16, val: u16) {
+        self.memory.write(addr, val);
+    }
+
+    /// Write `value` in every cell of `range`, bypassing the memory mapped devices
+    pub fn fill_mem(&mut self, range: Range<u16>, value: u16) {
+        self.memory.mem[range.start as usize..range.end as usize].fill(value);
+    }
+
+    /// Value of `register`.
+    ///
+    /// ```
+    /// use toy_vm::{words_to_obj, Register, VM};
+    ///
+    /// let mut vm = VM::<&[u8], Vec<u8>>::default();
+    /// // ADD R0, R1, #2 then HALT
+    /// vm.load(&words_to_obj(0x3000, &[0x1062, 0xF025])[..]).unwrap();
+    /// vm.set_register(Register::R1, 40);
+    /// vm.run();
+    /// assert_eq!(vm.register(Register::R0), 42);
+    /// ```
+    pub fn register(&self, register: Register) -> u16 {
+        self.reg(register.into())
+    }
+
+    /// Overwrite `register`, the condition codes aren't updated
+    pub fn set_register(&mut self, register: Register, value: u16) {
+        self.set_reg(register.into(), value);
+    }
+
+    /// Processor status register: privilege in bit 15, priority in [10:8] and NZP in [2:0]
+    pub fn psr(&self) -> u16 {
+        self.psr | self.reg(Reg::RCond)
+    }
+
+    /// Whether the program runs in user mode, bit 15 of the PSR. The VM starts in supervisor
+    /// mode, RTI may return to user mode
+    pub fn user_mode(&self) -> bool {
+        self.psr >> 15 == 1
+    }
+
+    /// Priority level of the running program, bits [10:8] of the PSR
+    pub fn priority(&self) -> u8 {
+        ((self.psr >> 8) & 0b111) as u8
+    }
+
+    pub fn set_priority(&mut self, level: u8) -> Result<(), InvalidPriority> {
+        if level > 7 {
+            return Err(InvalidPriority(level));
+        }
+        self.psr = (self.psr & !(0b111 << 8)) | (level as u16) << 8;
+        Ok(())
+    }
+
+    /// Raise the interrupt `vector` with `priority` once [`VM::instruction_count`] reaches
+    /// `at_instruction`, for reproducible tests of interrupt handlers. It is taken before the
+    /// next fetch as soon as its priority is above the running program's, until then it stays
+    /// pending. Taking it pushes the PSR and the PC on the R6 stack, switches to supervisor
+    /// mode at `priority` and jumps to the entry x0100 + `vector` of the interrupt vector table,
+    /// the handler returns with RTI
+    pub fn schedule_interrupt(
+        &mut self,
+        at_instruction: u128,
+        vector: u16,
+        priority: u8,
+    ) -> Result<(), InvalidPriority> {
+        if priority > 7 {
+            return Err(InvalidPriority(priority));
+        }
+        self.scheduled_interrupts.push(ScheduledInterrupt {
+            at_instruction,
+            vector: vector & 0xFF,
+            priority,
+        });
+        Ok(())
+    }
+
+    /// Copy `len` words from `src` to `dst` like `memmove`, overlapping regions are handled.
+    /// Bypasses the memory mapped devices, a region going past xFFFF wraps around to x0000
+    pub fn copy_mem(&mut self, src: u16, dst: u16, len: u16) {
+        let words: Vec<u16> = (0..len)
+            .map(|i| self.memory.mem[src.wrapping_add(i) as usize])
+            .collect();
+        for (i, word) in (0..len).zip(words) {
+            self.memory.mem[dst.wrapping_add(i) as usize] = word;
+        }
+    }
+
+    /// Instructions executed since the VM was created, the counter behind the instruction timer
+    pub fn instruction_count(&self) -> u128 {
+        self.memory.timer.ticks
+    }
+
+    /// How many times each opcode was executed, indexed by the opcode (bits [15:12])
+    pub fn opcode_histogram(&self) -> &[u64; 16] {
+        &self.opcode_histogram
+    }
+
+    /// How many times each trap vector was executed
+    pub fn trap_histogram(&self) -> &HashMap<u8, u64> {
+        &self.trap_histogram
+    }
+
+    /// The `top` most executed addresses with their count, most executed first.
+    /// Empty unless [`VmConfig::profile_addresses`] is set
+    pub fn hot_addresses(&self, top: usize) -> Vec<(u16, u64)> {
+        let mut hot: Vec<(u16, u64)> = self
+            .address_counts
+            .iter()
+            .map(|(addr, count)| (*addr, *count))
+            .collect();
+        hot.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        hot.truncate(top);
+        hot
+    }
+
+    pub fn config(&self) -> &VmConfig {
+        &self.config
+    }
+
+    pub fn set_config(&mut self, config: VmConfig) {
+        self.memory.framebuffer = config.framebuffer.clone();
+        self.memory.timer.source = config.timer_source;
+        self.config = config;
+    }
+
+    /// The memory region configured as a display, empty without [`VmConfig::framebuffer`]
+    pub fn framebuffer(&self) -> &[u16] {
+        match &self.memory.framebuffer {
+            Some(range) => &self.memory.mem[range.start as usize..range.end as usize],
+            None => &[],
+        }
+    }
+
+    /// Whether the framebuffer was written since the last call, so a GUI only redraws when needed
+    pub fn take_framebuffer_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.memory.framebuffer_dirty)
+    }
+
+    /// Load done by an instruction, recorded when tracing memory accesses
+    fn mem_read(&mut self, address: u16) -> u16 {
+        let value = self.bus_read(address);
+        self.record_access(address, AccessKind::Read, value);
+        value
+    }
+
+    /// Read going through the devices, polling the reader when the keyboard status is read.
+    /// A reader error stops the VM with [`VmError::Io`] and reads as no key available
+    fn bus_read(&mut self, address: u16) -> u16 {
+        if self.config.bus_mode == BusMode::Strict
+            && address >= DEVICE_PAGE
+            && !DEVICES.contains(&address)
+        {
+            self.fault = Some(RunResult::Error(VmError::BusError(address)));
+            return 0;
+        }
+        if address == MR_KBSR {
+            match self.read_byte() {
+                Ok(None) => self.memory.write(MR_KBSR, 0),
+                Ok(Some(byte)) => {
+                    self.memory.write(MR_KBSR, 1 << 15);
+                    self.memory.write(MR_KBDR, byte as u16);
+                }
+                Err(e) => {
+                    self.memory.write(MR_KBSR, 0);
+                    self.fault = Some(RunResult::Error(VmError::Io(e.kind())));
+                }
+            }
+        }
+        self.memory.read_device(address)
+    }
+
+    /// Next byte of the input, `None` when there is none, recorded when recording the input
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let mut buf = [0; 1];
+        if self.reader.read(&mut buf)? == 0 {
+            return Ok(None);
+        }
+        if let Some(recorded) = &mut self.recorded_input {
+            recorded.push(buf[0]);
+        }
+        Ok(Some(buf[0]))
+    }
+
+    /// Store done by an instruction, checked against the protected addresses
+    fn mem_write(&mut self, address: u16, val: u16) {
+        if self.config.protect_zero_page && address <= 0x00FF {
+            self.fault = Some(RunResult::ProtectionViolation { addr: address });
+            return;
+        }
+        self.memory.write(address, val);
+        self.record_access(address, AccessKind::Write, val);
+        if self.watchpoints.contains(&address) {
+            self.fault = Some(RunResult::Watchpoint { addr: address });
+        }
+    }
+
+    fn record_access(&mut self, addr: u16, kind: AccessKind, value: u16) {
+        if let Some(accesses) = &mut self.mem_accesses {
+            accesses.push(MemAccess {
+                pc: self.current_pc,
+                addr,
+                kind,
+                value,
+            });
+        }
+    }
+
+    fn inc_rpc(&mut self) -> u16 {
+        let next_addr = self.reg(Reg::RPC).wrapping_add(1);
+        self.set_reg(Reg::RPC, next_addr);
+        next_addr
+    }
+
+    /// Save the return address of a native trap according to [`VmConfig::trap_return`]
+    fn link_trap(&mut self) {
+        if self.config.trap_return == TrapReturn::Ret {
+            let rpc = self.get_rpc();
+            self.set_reg(Reg::R7, rpc);
+        }
+    }
+
+    /// Push on the stack pointed by R6, `None` when it would overflow the supervisor stack
+    fn push(&mut self, value: u16) -> Option<()> {
+        let sp = self.reg(Reg::R6);
+        let top = sp.wrapping_sub(1);
+        if !self.in_supervisor_stack(top) {
+            self.fault = Some(RunResult::Error(VmError::StackFault(sp)));
+            return None;
+        }
+        self.set_reg(Reg::R6, top);
+        self.mem_write(top, value);
+        Some(())
+    }
+
+    /// Pop from the stack pointed by R6, `None` when the supervisor stack is empty
+    fn pop(&mut self) -> Option<u16> {
+        let sp = self.reg(Reg::R6);
+        if !self.in_supervisor_stack(sp) {
+            self.fault = Some(RunResult::Error(VmError::StackFault(sp)));
+            return None;
+        }
+        let value = self.mem_read(sp);
+        self.set_reg(Reg::R6, sp.wrapping_add(1));
+        Some(value)
+    }
+
+    fn in_supervisor_stack(&self, addr: u16) -> bool {
+        self.supervisor_stack
+            .as_ref()
+            .is_none_or(|range| range.contains(&addr))
+    }
+
+    /// Take the most urgent due interrupt of [`VM::schedule_interrupt`] its priority allows
+    fn enter_scheduled_interrupt(&mut self) {
+        let now = self.instruction_count();
+        let running = self.priority();
+        let Some(index) = self
+            .scheduled_interrupts
+            .iter()
+            .enumerate()
+            .filter(|(_, i)| i.at_instruction <= now && i.priority > running)
+            .max_by_key(|(_, i)| i.priority)
+            .map(|(index, _)| index)
+        else {
+            return;
+        };
+        let interrupt = self.scheduled_interrupts.remove(index);
+
+        let psr = self.psr();
+        let pc = self.get_rpc();
+        if self.push(psr).and_then(|_| self.push(pc)).is_none() {
+            return;
+        }
+        self.psr = (interrupt.priority as u16) << 8;
+        let handler = self.mem_read(INTERRUPT_VECTOR_TABLE + interrupt.vector);
+        self.set_reg(Reg::RPC, handler);
+    }
+
+    fn set_psr(&mut self, psr: u16) {
+        self.psr = psr & !0b111;
+        self.set_reg(Reg::RCond, psr & 0b111);
+    }
+
+    /// Run the loop registered by [`VM::accelerate_multiply`] at `addr`, `false` when the code
+    /// or the registers don't match the pattern and it must be interpreted
+    fn run_multiply_loop(&mut self, addr: u16) -> bool {
+        let add = self.memory.read(addr);
+        let dec = self.memory.read(addr.wrapping_add(1));
+        let br = self.memory.read(addr.wrapping_add(2));
+        let (acc, m, c) = (Reg::dr(add), Reg::sr2(add), Reg::dr(dec));
+        let matches = add >> 12 == 0b0001
+            && add & 0b111000 == 0
+            && Reg::sr1(add) == acc
+            && dec >> 12 == 0b0001
+            && dec & 0b111111 == 0b1_11111
+            && Reg::sr1(dec) == c
+            && br == 0x03FD // BRp #-3
+            && acc != m
+            && acc != c
+            && m != c;
+        let count = self.reg(c);
+        if !matches || count as i16 <= 0 {
+            return false;
+        }
+
+        let product = self.reg(m).wrapping_mul(count);
+        self.set_reg(acc, self.reg(acc).wrapping_add(product));
+        self.set_reg(c, 0);
+        self.set_nzp(&c);
+        self.set_reg(Reg::RPC, addr.wrapping_add(3));
+        self.current_pc = addr.wrapping_add(2);
+
+        let iterations = count as u64;
+        self.memory.timer.ticks += 3 * iterations as u128;
+        self.opcode_histogram[0b0001] += 2 * iterations;
+        self.opcode_histogram[0b0000] += iterations;
+        if self.config.profile_addresses {
+            for offset in 0..3 {
+                *self
+                    .address_counts
+                    .entry(addr.wrapping_add(offset))
+                    .or_default() += iterations;
+            }
+        }
+        true
+    }
+
+    fn pace(&mut self) {
+        let Some(clock) = &mut self.clock else {
+            return;
+        };
+        clock.executed += 1;
+        if clock.executed % PACE_INTERVAL == 0 {
+            let expected = Duration::from_secs_f64(clock.executed as f64 / clock.hz as f64);
+            if let Some(ahead) = expected.checked_sub(clock.start.elapsed()) {
+                std::thread::sleep(ahead);
+            }
+        }
+    }
+
+    /// Report a move of R6 outside the stack range, `sp` is its value before the instruction
+    fn check_stack(&mut self, pc: u16, sp: u16) {
+        let Some(range) = &self.stack_range else {
+            return;
+        };
+        let new_sp = self.reg(Reg::R6);
+        if new_sp != sp && !range.contains(&new_sp) {
+            self.trace(TraceEvent::StackOutOfRange { pc, sp: new_sp });
+        }
+    }
+
+    fn trace(&mut self, event: TraceEvent) {
+        if let Some(hook) = &mut self.trace_hook {
+            hook(event);
+        }
+    }
+
+    /// Output of a trap: written to the writer and given byte by byte to the output sink
+    fn output(&mut self, bytes: &[u8]) {
+        if self.config.null_io.is_some() {
+            return;
+        }
+        let bytes = match self.config.max_output_bytes {
+            Some(max) if self.output_bytes + bytes.len() > max => {
+                if self.config.output_overflow == OutputOverflow::Halt {
+                    self.fault = Some(RunResult::OutputLimitReached);
+                }
+                &bytes[..max.saturating_sub(self.output_bytes)]
+            }
+            _ => bytes,
+        };
+        self.output_bytes += bytes.len();
+        if let Err(e) = self.writer.write_all(bytes) {
+            self.fault = Some(RunResult::Error(VmError::Io(e.kind())));
+            return;
+        }
+        if let Some(sink) = &mut self.output_sink {
+            bytes.iter().for_each(|b| sink(*b));
+        }
+    }
+
+    /// Flush the writer after a trap output, a failure stops the VM with [`VmError::Io`]
+    fn flush_output(&mut self) {
+        if let Err(e) = self.writer.flush() {
+            self.fault = Some(RunResult::Error(VmError::Io(e.kind())));
+        }
+    }
+
+    /// Value of a register read as a two's complement number
+    fn register_signed(&self, r: Reg) -> i16 {
+        self.reg(r) as i16
+    }
+
+    fn reg(&self, r: Reg) -> u16 {
+        self.registers[r as usize]
+    }
+
+    fn set_reg(&mut self, r: Reg, value: u16) {
+        self.registers[r as usize] = value;
+    }
+
+    fn get_rpc(&self) -> u16 {
+        self.reg(Reg::RPC)
+    }
+    fn set_nzp(&mut self, r: &Reg) {
+        if self.reg(*r) == 0 {
+            self.set_reg(Reg::RCond, 1 << 1);
+        } else if self.reg(*r) >> 15 == 1 {
+            self.set_reg(Reg::RCond, 1 << 2);
+        } else {
+            self.set_reg(Reg::RCond, 1 << 0);
+        }
+    }
+}
+
+/// Flush the output a buffered writer may still hold, e.g. when a debugger drops a VM
+/// stopped on a breakpoint. A failing flush is ignored
+impl<R, W> Drop for VM<R, W>
+where
+    R: Read,
+    W: Write,
+{
+    fn drop(&mut self) {
+        let _ = self.writer.flush();
+    }
+}
+
+/// Number of bytes shown on each side of the first difference in an [`OutputMismatch`]
+const MISMATCH_CONTEXT: usize = 8;
+
+/// First difference between the output of a VM and the expected one
+#[derive(Debug, PartialEq, Eq)]
+pub struct OutputMismatch {
+    /// Index of the first differing byte
+    pub index: usize,
+    /// Bytes of the expected output around `index`
+    pub expected: Vec<u8>,
+    /// Bytes of the actual output around `index`
+    pub found: Vec<u8>,
+}
+
+impl fmt::Display for OutputMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "output differs at byte {}: expected {:?}, found {:?}",
+            self.index,
+            String::from_utf8_lossy(&self.expected),
+            String::from_utf8_lossy(&self.found)
+        )
+    }
+}
+
+impl Error for OutputMismatch {}
+
+impl<R> VM<R, Vec<u8>>
+where
+    R: Read,
+{
+    /// Compare everything written so far with `expected`, reporting the first differing byte
+    pub fn assert_output(&self, expected: &[u8]) -> Result<(), OutputMismatch> {
+        let output = self.writer.as_slice();
+        if output == expected {
+            return Ok(());
+        }
+
+        let index = output
+            .iter()
+            .zip(expected)
+            .position(|(a, b)| a != b)
+            .unwrap_or(output.len().min(expected.len()));
+        let context = |bytes: &[u8]| {
+            let start = index.saturating_sub(MISMATCH_CONTEXT);
+            let end = (index + MISMATCH_CONTEXT).min(bytes.len());
+            bytes.get(start..end).unwrap_or_default().to_vec()
+        };
+
+        Err(OutputMismatch {
+            index,
+            expected: context(expected),
+            found: context(output),
+        })
+    }
+}
+
+/// Steps the VM on each `next()`, yielding every [`StepResult`] up to and including the
+/// halt or fault, for `for step in vm.stepper()` loops.
+///
+/// The VM stays reachable through [`Stepper::vm`] to inspect its state between steps.
+pub struct Stepper<'a, R, W>
+where
+    R: Read,
+    W: Write,
+{
+    vm: &'a mut VM<R, W>,
+    done: bool,
+}
+
+impl<R, W> Stepper<'_, R, W>
+where
+    R: Read,
+    W: Write,
+{
+    pub fn vm(&self) -> &VM<R, W> {
+        self.vm
+    }
+}
+
+impl<R, W> Iterator for Stepper<'_, R, W>
+where
+    R: Read,
+    W: Write,
+{
+    type Item = StepResult;
+
+    fn next(&mut self) -> Option<StepResult> {
+        if self.done {
+            return None;
+        }
+        let result = self.vm.step();
+        self.done = !result.is_running();
+        Some(result)
+    }
+}
+
+impl VM<LibCReader, Stdout> {
+    /// A VM wired to the terminal: keyboard through [`LibCReader`] and display on stdout.
+    ///
+    /// ```no_run
+    /// use toy_vm::{unsafe_zone, VM};
+    ///
+    /// unsafe_zone::disable_input_buffering();
+    /// let mut vm = VM::stdio();
+    /// vm.load(std::fs::File::open("2048.obj").unwrap()).unwrap();
+    /// vm.run();
+    /// unsafe_zone::restore_input_buffering();
+    /// ```
+    pub fn stdio() -> Self {
+        Self::default()
+    }
+}
+
+impl Default for VM<LibCReader, Stdout> {
+    fn default() -> Self {
+        Self::new(LibCReader, io::stdout())
+    }
+}
+
+impl<'a> VM<&'a [u8], Vec<u8>> {
+    /// A VM reading `input`, e.g. the [`VM::recorded_input`] of a session, to run the same
+    /// program again with the same input and so the same output
+    pub fn replay(input: &'a [u8]) -> Self {
+        Self::new(input, Vec::new())
+    }
+}
+
+impl Default for VM<&[u8], Vec<u8>> {
+    fn default() -> Self {
+        Self::new(b"", Vec::default())
+    }
+}
+
+struct Memory {
+    mem: [u16; u16::MAX as usize + 1],
+    framebuffer: Option<Range<u16>>,
+    framebuffer_dirty: bool,
+    timer: Timer,
+}
+
+/// Sets the ready bit of TMR each time the interval written in TMI has elapsed
+struct Timer {
+    source: TimerSource,
+    ticks: u128,
+    started: Instant,
+    last_ready: u128,
+}
+
+impl Timer {
+    fn now(&self) -> u128 {
+        match self.source {
+            TimerSource::Instructions => self.ticks,
+            TimerSource::WallClock => self.started.elapsed().as_millis(),
+        }
+    }
+
+    fn ready(&mut self, interval: u16) -> bool {
+        let now = self.now();
+        if interval != 0 && now - self.last_ready >= interval as u128 {
+            self.last_ready = now;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl Default for Timer {
+    fn default() -> Self {
+        Self {
+            source: TimerSource::default(),
+            ticks: 0,
+            started: Instant::now(),
+            last_ready: 0,
+        }
+    }
+}
+
+impl Memory {
+    /// Read a word as it is stored, without the side effects of the devices
+    fn read(&self, address: u16) -> u16 {
+        self.mem[address as usize]
+    }
+
+    /// Read a word, updating the timer status first. The keyboard is handled by [`VM::mem_read`]
+    fn read_device(&mut self, address: u16) -> u16 {
+        if address == MR_TMR {
+            let interval = self.mem[MR_TMI as usize];
+            let status = if self.timer.ready(interval) {
+                1 << 15
+            } else {
+                0
+            };
+            self.write(MR_TMR, status);
+        }
+        self.mem[address as usize]
+    }
+
+    fn write(&mut self, address: u16, val: u16) {
+        self.mem[address as usize] = val;
+        if let Some(framebuffer) = &self.framebuffer {
+            if framebuffer.contains(&address) {
+                self.framebuffer_dirty = true;
+            }
+        }
+    }
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        let mut mem = [0; u16::MAX as usize + 1];
+        // The clock enable bit of the machine control register, cleared on HALT
+        mem[MR_MCR as usize] = 1 << 15;
+        Self {
+            mem,
+            framebuffer: None,
+            framebuffer_dirty: false,
+            timer: Timer::default(),
+        }
+    }
+}
+
+/// A register, for reading and writing it from outside the VM with [`VM::register`] and
+/// [`VM::set_register`]
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Register {
+    R0,
+    R1,
+    R2,
+    R3,
+    R4,
+    R5,
+    R6,
+    R7,
+    #[cfg(feature = "extended-registers")]
+    R8,
+    #[cfg(feature = "extended-registers")]
+    R9,
+    #[cfg(feature = "extended-registers")]
+    R10,
+    #[cfg(feature = "extended-registers")]
+    R11,
+    #[cfg(feature = "extended-registers")]
+    R12,
+    #[cfg(feature = "extended-registers")]
+    R13,
+    #[cfg(feature = "extended-registers")]
+    R14,
+    #[cfg(feature = "extended-registers")]
+    R15,
+    Pc,
+    /// Condition codes, N in bit 2, Z in bit 1 and P in bit 0
+    Cond,
+}
+
+impl From<Register> for Reg {
+    fn from(register: Register) -> Self {
+        match register {
+            Register::R0 => Reg::R0,
+            Register::R1 => Reg::R1,
+            Register::R2 => Reg::R2,
+            Register::R3 => Reg::R3,
+            Register::R4 => Reg::R4,
+            Register::R5 => Reg::R5,
+            Register::R6 => Reg::R6,
+            Register::R7 => Reg::R7,
+            #[cfg(feature = "extended-registers")]
+            Register::R8 => Reg::R8,
+            #[cfg(feature = "extended-registers")]
+            Register::R9 => Reg::R9,
+            #[cfg(feature = "extended-registers")]
+            Register::R10 => Reg::R10,
+            #[cfg(feature = "extended-registers")]
+            Register::R11 => Reg::R11,
+            #[cfg(feature = "extended-registers")]
+            Register::R12 => Reg::R12,
+            #[cfg(feature = "extended-registers")]
+            Register::R13 => Reg::R13,
+            #[cfg(feature = "extended-registers")]
+            Register::R14 => Reg::R14,
+            #[cfg(feature = "extended-registers")]
+            Register::R15 => Reg::R15,
+            Register::Pc => Reg::RPC,
+            Register::Cond => Reg::RCond,
+        }
+    }
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+enum Reg {
+    R0,
+    R1,
+    R2,
+    R3,
+    R4,
+    R5,
+    R6,
+    R7,
+    #[cfg(feature = "extended-registers")]
+    R8,
+    #[cfg(feature = "extended-registers")]
+    R9,
+    #[cfg(feature = "extended-registers")]
+    R10,
+    #[cfg(feature = "extended-registers")]
+    R11,
+    #[cfg(feature = "extended-registers")]
+    R12,
+    #[cfg(feature = "extended-registers")]
+    R13,
+    #[cfg(feature = "extended-registers")]
+    R14,
+    #[cfg(feature = "extended-registers")]
+    R15,
+    RPC,
+    RCond,
+}
+
+impl Reg {
+    fn dr(instruction: u16) -> Self {
+        let reg_nb = (instruction >> 9) & 0b0000000000000111;
+        reg_nb.into()
+    }
+    fn sr1(instruction: u16) -> Self {
+        let reg_nb = (instruction >> 6) & 0b0000000000000111;
+        reg_nb.into()
+    }
+    fn sr2(instruction: u16) -> Self {
+        let reg_nb = instruction & 0b0000000000000111;
+        reg_nb.into()
+    }
+}
+
+/// Every register at its reset value, the PC on [`PC_START`] and the Z flag set
+fn initial_registers() -> [u16; REG_COUNT] {
+    let mut registers = [0; REG_COUNT];
+    registers[Reg::RCond as usize] = 1 << 1;
+    registers[Reg::RPC as usize] = PC_START as u16;
+    registers
+}
+
+impl From<u16> for Reg {
+    fn from(value: u16) -> Self {
+        match value {
+            0 => Self::R0,
+            1 => Self::R1,
+            2 => Self::R2,
+            3 => Self::R3,
+            4 => Self::R4,
+            5 => Self::R5,
+            6 => Self::R6,
+            7 => Self::R7,
+            #[cfg(feature = "extended-registers")]
+            8 => Self::R8,
+            #[cfg(feature = "extended-registers")]
+            9 => Self::R9,
+            #[cfg(feature = "extended-registers")]
+            10 => Self::R10,
+            #[cfg(feature = "extended-registers")]
+            11 => Self::R11,
+            #[cfg(feature = "extended-registers")]
+            12 => Self::R12,
+            #[cfg(feature = "extended-registers")]
+            13 => Self::R13,
+            #[cfg(feature = "extended-registers")]
+            14 => Self::R14,
+            #[cfg(feature = "extended-registers")]
+            15 => Self::R15,
+            _ => panic!("The number {value} is not in [0..{}]", GPR_COUNT - 1),
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unusual_byte_groupings)]
+mod tests {
+
+    use std::io::BufReader;
+
+    use super::*;
+
+    #[test]
+    fn test_load_and_run() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+
+        let program: &[u16] = &[
+            0x3000,             // start = 0x3000; // 00110000 00000000
+            0b0001001001100011, // add r1/0 and 3 in r1/3
+            0b0001010010100100, // add r2/0 and 4 in r2/4
+            0b0001000001000010, // add r1/3 and r2/4 in r0/7
+            0b0101001001100001, // and r1/3 and 1 in r1/1
+            0b0101111000000010, // and r0/7 and r2/4 in r7/4
+            0b0010101000000010, // ld offset 2 DATA/718 in r5/718
+            0b1111000000100101, // halt
+            0,
+            0b0000001011001110, // DATA/718
+        ];
+
+        let res = words_to_obj(program[0], &program[1..]);
+
+        let reader = BufReader::new(res.as_slice());
+
+        vm.load(reader).unwrap();
+
+        let nb_i = vm.run();
+
+        assert_eq!(vm.reg(Reg::R0), 7);
+        assert_eq!(vm.reg(Reg::R1), 1);
+        assert_eq!(vm.reg(Reg::R2), 4);
+        assert_eq!(vm.reg(Reg::R3), 0);
+        assert_eq!(vm.reg(Reg::R4), 0);
+        assert_eq!(vm.reg(Reg::R5), 718);
+        assert_eq!(vm.reg(Reg::R6), 0);
+        assert_eq!(vm.reg(Reg::R7), 4);
+        assert_eq!(nb_i, 6 + 1); // the six instructions before the HALT, then the HALT
+        assert_eq!(vm.instruction_count(), nb_i);
+        assert_eq!(vm.run(), 0); // already halted
+    }
+
+    #[test]
+    fn test_step() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(
+            &mut vm,
+            &[
+                0x3000,
+                0b0001001001100011, // add r1/0 and 3 in r1/3
+                0b0001010010100100, // add r2/0 and 4 in r2/4
+                0b0001000001000010, // add r1/3 and r2/4 in r0/7
+                0b1111000000100001, // out
+                0b1111000000100101, // halt
+            ],
+        );
+
+        assert_eq!(vm.step(), StepResult::Continue);
+        assert_eq!((vm.reg(Reg::R1), vm.get_rpc()), (3, 0x3001));
+        assert_eq!(vm.step(), StepResult::Continue);
+        assert_eq!((vm.reg(Reg::R2), vm.get_rpc()), (4, 0x3002));
+        assert_eq!(vm.step(), StepResult::Continue);
+        assert_eq!((vm.reg(Reg::R0), vm.get_rpc()), (7, 0x3003));
+        assert_eq!(vm.step(), StepResult::Trap(0x21));
+        assert_eq!(vm.writer, [7]);
+        assert_eq!(vm.step(), StepResult::Halted);
+        assert_eq!(vm.step(), StepResult::Halted);
+        assert_eq!(vm.instruction_count(), 5);
+    }
+
+    #[test]
+    fn test_assert_output() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.writer.extend_from_slice(b"Hello World");
+
+        assert_eq!(vm.assert_output(b"Hello World"), Ok(()));
+
+        let mismatch = vm.assert_output(b"Hello Wxrld").unwrap_err();
+        assert_eq!(mismatch.index, 7);
+        assert_eq!(mismatch.expected, b"Hello Wxrld".to_vec());
+        assert_eq!(mismatch.found, b"Hello World".to_vec());
+
+        let mismatch = vm.assert_output(b"Hello World!").unwrap_err();
+        assert_eq!(mismatch.index, 11);
+        assert_eq!(mismatch.expected, b"lo World!".to_vec());
+    }
+
+    #[test]
+    fn test_current_word() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(&mut vm, SUBROUTINE_PROGRAM);
+        assert_eq!(vm.current_word(), SUBROUTINE_PROGRAM[1]);
+        assert_eq!(vm.get_rpc(), 0x3000);
+
+        vm.step();
+        assert_eq!(vm.current_word(), SUBROUTINE_PROGRAM[4]);
+    }
+
+    #[test]
+    fn test_cc_string() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        assert_eq!(vm.cc_string(), "Z");
+        for (cond, expected) in [
+            (0b100, "N"),
+            (0b010, "Z"),
+            (0b001, "P"),
+            (0, "?"),
+            (0b011, "?"),
+        ] {
+            vm.set_reg(Reg::RCond, cond);
+            assert_eq!(vm.cc_string(), expected);
+        }
+
+        vm.set_reg(Reg::R0, 0x8000);
+        vm.set_nzp(&Reg::R0);
+        assert_eq!(vm.cc_string(), "N");
+    }
+
+    #[cfg(not(feature = "extended-registers"))]
+    #[test]
+    fn test_dump_registers() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.set_reg(Reg::R3, 0xBEEF);
+
+        assert_eq!(
+            vm.dump_registers(),
+            "R0=x0000 R1=x0000 R2=x0000 R3=xBEEF R4=x0000 R5=x0000 R6=x0000 R7=x0000 PC=x3000 COND=x0002"
+        );
+    }
+
+    #[test]
+    fn test_mips() {
+        assert_eq!(mips(2_000_000, Duration::from_secs(1)), 2.0);
+        assert_eq!(mips(500_000, Duration::from_millis(250)), 2.0);
+        assert_eq!(mips(0, Duration::from_secs(3)), 0.0);
+    }
+
+    #[test]
+    fn test_fill_mem() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.fill_mem(0x4000..0x4010, 0xABCD);
+
+        assert_eq!(vm.memory.read(0x3FFF), 0);
+        assert_eq!(vm.memory.read(0x4000), 0xABCD);
+        assert_eq!(vm.memory.read(0x4008), 0xABCD);
+        assert_eq!(vm.memory.read(0x400F), 0xABCD);
+        assert_eq!(vm.memory.read(0x4010), 0);
+    }
+
+    #[test]
+    fn test_set_entry() {
+        let program = &[
+            0x3000,
+            0b0001_000_000_1_00001, // add r0 and 1 in r0
+            0b1111000000100101,     // halt
+            0b0001_000_000_1_00010, // add r0 and 2 in r0
+            0b1111000000100101,     // halt
+            0b0001_000_000_1_00011, // add r0 and 3 in r0
+            0b0001_000_000_1_00100, // x3005: add r0 and 4 in r0
+            0b1111000000100101,     // halt
+        ];
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(&mut vm, program);
+        assert_eq!(vm.set_entry(0x3005), Ok(()));
+        vm.run();
+        assert_eq!(vm.reg(Reg::R0), 4);
+
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(&mut vm, program);
+        vm.set_config(VmConfig {
+            strict: true,
+            ..VmConfig::default()
+        });
+        assert_eq!(vm.set_entry(0x3007), Err(EntryOutsideImage(0x3007)));
+        assert_eq!(vm.get_rpc(), 0x3000);
+        assert_eq!(vm.set_entry(0x3006), Ok(()));
+    }
+
+    #[test]
+    fn test_priority() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        assert_eq!(vm.priority(), 0);
+        assert_eq!(vm.psr(), 0b010);
+
+        assert_eq!(vm.set_priority(5), Ok(()));
+        assert_eq!(vm.priority(), 5);
+        assert_eq!(vm.psr(), 0b0000_0101_0000_0010);
+
+        assert_eq!(vm.set_priority(8), Err(InvalidPriority(8)));
+        assert_eq!(vm.priority(), 5);
+
+        assert_eq!(vm.set_priority(0), Ok(()));
+        assert_eq!(vm.psr(), 0b010);
+    }
+
+    #[test]
+    fn test_copy_mem() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        for (i, addr) in (0x4000..0x4005).enumerate() {
+            vm.memory.mem[addr] = i as u16 + 1;
+        }
+
+        vm.copy_mem(0x4000, 0x4002, 5);
+        assert_eq!(&vm.memory.mem[0x4000..0x4007], &[1, 2, 1, 2, 3, 4, 5]);
+
+        vm.copy_mem(0x4002, 0x4000, 5);
+        assert_eq!(&vm.memory.mem[0x4000..0x4007], &[1, 2, 3, 4, 5, 4, 5]);
+
+        vm.copy_mem(0x4000, 0x5000, 0);
+        assert_eq!(vm.memory.mem[0x5000], 0);
+
+        // Both regions wrap past xFFFF
+        vm.copy_mem(0x4000, 0xFFFE, 3);
+        assert_eq!(vm.memory.mem[0xFFFE..], [1, 2]);
+        assert_eq!(vm.memory.mem[0x0000], 3);
+        vm.copy_mem(0xFFFF, 0x6000, 2);
+        assert_eq!(vm.memory.mem[0x6000..0x6002], [2, 3]);
+    }
+
+    #[test]
+    fn test_framebuffer() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        assert!(vm.framebuffer().is_empty());
+
+        vm.set_config(VmConfig {
+            framebuffer: Some(0xC000..0xC010),
+            ..VmConfig::default()
+        });
+
+        vm.memory.write(0x4000, 1);
+        assert!(!vm.take_framebuffer_dirty());
+
+        vm.memory.write(0xC002, 0x7C00);
+        assert_eq!(vm.framebuffer().len(), 0x10);
+        assert_eq!(vm.framebuffer()[2], 0x7C00);
+        assert!(vm.take_framebuffer_dirty());
+        assert!(!vm.take_framebuffer_dirty());
+    }
+
+    #[test]
+    fn test_relocate() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(
+            &mut vm,
+            &[
+                0x3000,             // start = 0x3000
+                0b0010101000000010, // ld offset 2 DATA/718 in r5/718
+                0b1111000000100101, // halt
+                0,
+                0b0000001011001110, // DATA/718
+            ],
+        );
+
+        assert_eq!(vm.relocate(0x100), Ok(()));
+
+        assert_eq!(vm.reg(Reg::RPC), 0x3100);
+        assert_eq!(vm.memory.read(0x3000), 0);
+        assert_eq!(vm.memory.read(0x3003), 0);
+        assert_eq!(vm.memory.read(0x3100), 0b0010101000000010);
+        assert_eq!(vm.memory.read(0x3103), 718);
+
+        vm.run();
+        assert_eq!(vm.reg(Reg::R5), 718);
+
+        // Overlapping move, the metadata of the image moves along
+        let assembled =
+            assembler::assemble(".ORIG x3000\nLD R5, DATA\nHALT\nDATA .FILL #718\n").unwrap();
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.load_assembled(&assembled).unwrap();
+        vm.add_breakpoint(0x3001);
+        vm.add_breakpoint(0x4000);
+        assert_eq!(vm.relocate(1), Ok(()));
+        assert_eq!(vm.memory.read(0x3000), 0);
+        assert_eq!(vm.memory.read(0x3001), 0b0010101000000001);
+        assert_eq!(vm.memory.read(0x3003), 718);
+        assert_eq!(vm.classify_address(0x3003), AddrKind::Data);
+        assert_eq!(vm.classify_address(0x3000), AddrKind::Unknown);
+        assert!(vm.breakpoints().contains(&0x3002));
+        assert!(vm.breakpoints().contains(&0x4000));
+        assert!(!vm.breakpoints().contains(&0x3001));
+        let mut listing = Vec::new();
+        vm.write_listing(&mut listing).unwrap();
+        assert!(String::from_utf8(listing)
+            .unwrap()
+            .contains("x3003  x02CE  DATA"));
+        assert_eq!(vm.resume(), RunResult::Breakpoint { pc: 0x3002 });
+        assert_eq!(vm.reg(Reg::R5), 718);
+
+        // The image must stay in memory, ending at xFFFF is fine
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.load_words(0x0002, &[1, 2]).unwrap();
+        assert_eq!(vm.relocate(-3), Err(RelocationOutOfMemory(-3)));
+        assert_eq!(vm.memory.read(0x0002), 1);
+        assert_eq!(vm.relocate(-2), Ok(()));
+        assert_eq!(vm.memory.read(0x0000), 1);
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.load_words(0x8000, &[1, 2]).unwrap();
+        assert_eq!(vm.relocate(0x7FFF), Err(RelocationOutOfMemory(0x7FFF)));
+        vm.load_words(0x7FFF, &[1, 2]).unwrap();
+        assert_eq!(vm.relocate(0x7FFF), Ok(()));
+        assert_eq!(vm.memory.read(0xFFFF), 2);
+    }
+
+    /// main calls a subroutine adding 6 to R0
+    const SUBROUTINE_PROGRAM: &[u16] = &[
+        0x3000,                 // start = 0x3000
+        0b0100_1_00000000010,   // jsr SUB
+        0b0001_001_001_1_00001, // add r1 and 1 in r1
+        0b1111000000100101,     // halt
+        0b0001_000_000_1_00101, // SUB: add r0 and 5 in r0
+        0b0001_000_000_1_00001, // add r0 and 1 in r0
+        0b1100_000_111_000000,  // ret
+    ];
+
+    #[test]
+    fn test_drop_flushes_output() {
+        /// Only shows the output once flushed, unlike `BufWriter` it doesn't flush on drop
+        struct Buffered {
+            pending: Vec<u8>,
+            flushed: std::rc::Rc<std::cell::RefCell<Vec<u8>>>,
+        }
+
+        impl Write for Buffered {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.pending.extend_from_slice(buf);
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                self.flushed.borrow_mut().append(&mut self.pending);
+                Ok(())
+            }
+        }
+
+        let flushed = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let writer = Buffered {
+            pending: Vec::new(),
+            flushed: flushed.clone(),
+        };
+        let mut vm = VmBuilder::new().build(&b""[..], writer);
+        vm.writer.write_all(b"pending").unwrap();
+        assert!(flushed.borrow().is_empty());
+
+        drop(vm);
+        assert_eq!(*flushed.borrow(), b"pending");
+    }
+
+    #[test]
+    fn test_accelerate_multiply() {
+        let program = &[
+            0x3000,
+            0b0010_001_000000110,    // ld r1 from 0x3007
+            0b0010_010_000000110,    // ld r2 from 0x3008
+            0b0101_000_000_1_00000,  // and r0 and 0 in r0
+            0b0001_000_000_0_00_001, // x3003 LOOP: add r0 and r1 in r0
+            0b0001_010_010_1_11111,  // add r2 and -1 in r2
+            0b0000_001_111111101,    // brp LOOP
+            0b1111000000100101,      // halt
+            7,
+            6,
+        ];
+        let config = VmConfig {
+            profile_addresses: true,
+            ..VmConfig::default()
+        };
+
+        let mut interpreted = VM::<&[u8], Vec<u8>>::default();
+        load_program(&mut interpreted, program);
+        interpreted.set_config(config.clone());
+        let interpreted_count = interpreted.run();
+
+        let mut accelerated = VM::<&[u8], Vec<u8>>::default();
+        load_program(&mut accelerated, program);
+        accelerated.set_config(config);
+        accelerated.accelerate_multiply(0x3003);
+        assert_eq!(accelerated.step(), StepResult::Continue);
+        assert_eq!(accelerated.run(), interpreted_count - 1);
+
+        assert_eq!(accelerated.registers, interpreted.registers);
+        assert_eq!(accelerated.reg(Reg::R0), 42);
+        assert_eq!(accelerated.instruction_count(), 3 + 6 * 3 + 1);
+        assert_eq!(
+            accelerated.instruction_count(),
+            interpreted.instruction_count()
+        );
+        assert_eq!(
+            accelerated.opcode_histogram(),
+            interpreted.opcode_histogram()
+        );
+        assert_eq!(accelerated.hot_addresses(8), interpreted.hot_addresses(8));
+    }
+
+    #[test]
+    fn test_diff_traces() {
+        let a: Vec<(u16, u16)> = (0..10).map(|i| (0x3000 + i, 0x1000 + i)).collect();
+        let mut b = a.clone();
+        assert_eq!(diff_traces(&a, &b), None);
+        assert_eq!(diff_traces_context(&a, &b, 2), None);
+
+        b[6].1 = 0xF025;
+        assert_eq!(diff_traces(&a, &b), Some(6));
+        assert_eq!(
+            diff_traces_context(&a, &b, 2),
+            Some(TraceDivergence {
+                index: 6,
+                a: a[4..9].to_vec(),
+                b: b[4..9].to_vec(),
+            })
+        );
+
+        assert_eq!(diff_traces(&a, &a[..7]), Some(7));
+        assert_eq!(diff_traces_context(&a, &a[..7], 1).unwrap().b, &a[6..7]);
+    }
+
+    #[test]
+    fn test_encode_program() {
+        use assembler::{encode_program, Op};
+
+        let words = encode_program(&[
+            Op::Jsr { offset: 2 },
+            Op::AddImm {
+                dr: 1,
+                sr1: 1,
+                imm: 1,
+            },
+            Op::Trap(0x25),
+            Op::AddImm {
+                dr: 0,
+                sr1: 0,
+                imm: 5,
+            },
+            Op::AddImm {
+                dr: 0,
+                sr1: 0,
+                imm: 1,
+            },
+            Op::Ret,
+        ]);
+        assert_eq!(words, &SUBROUTINE_PROGRAM[1..]);
+    }
+
+    #[test]
+    fn test_run_on_thread() {
+        let handle = std::thread::spawn(|| {
+            let mut vm = VM::<&[u8], Vec<u8>>::default();
+            load_program(&mut vm, SUBROUTINE_PROGRAM);
+            vm.run();
+            vm
+        });
+
+        let vm = handle.join().unwrap();
+        assert_eq!(vm.reg(Reg::R0), 6);
+        assert_eq!(vm.reg(Reg::R1), 1);
+    }
+
+    #[test]
+    fn test_run_report() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(&mut vm, SUBROUTINE_PROGRAM);
+
+        assert_eq!(
+            vm.run_report(),
+            RunReport {
+                result: RunResult::Halted,
+                instructions: 6,
+                r0: 6
+            }
+        );
+
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(&mut vm, &[0x3000, 0b1111_0000_00110000]);
+        assert_eq!(
+            vm.run_report().result,
+            RunResult::Error(VmError::BadTrap(0x30))
+        );
+    }
+
+    #[test]
+    fn test_custom_opcode_handler() {
+        let program = &[
+            0x3000,
+            0b1101_000_000101010, // custom: r0 = 42
+            0b1111000000100101,   // halt
+        ];
+
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(&mut vm, program);
+        assert_eq!(
+            vm.run_report().result,
+            RunResult::Error(VmError::IllegalOpcode(0xD02A))
+        );
+
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(&mut vm, program);
+        vm.set_custom_opcode_handler(Box::new(|vm, word| {
+            vm.set_reg(Reg::R0, word & 0x1FF);
+        }));
+        assert_eq!(vm.run_report().result, RunResult::Halted);
+        assert_eq!(vm.reg(Reg::R0), 42);
+    }
+
+    #[test]
+    fn test_ran_off_end() {
+        let program = &[
+            0x3000,
+            0b0001_000_000_1_00001, // add r0 and 1 in r0
+            0b0001_000_000_1_00001, // add r0 and 1 in r0, no halt
+        ];
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(&mut vm, program);
+        vm.set_config(VmConfig {
+            strict: true,
+            ..VmConfig::default()
+        });
+        assert_eq!(vm.run_report().result, RunResult::RanOffEnd { pc: 0x3002 });
+        assert_eq!(vm.reg(Reg::R0), 2);
+        assert_eq!(vm.instruction_count(), 2);
+    }
+
+    #[test]
+    fn test_bad_trap() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(&mut vm, &[0x3000, 0b1111_0000_00101100]); // dump registers
+        vm.set_config(VmConfig {
+            strict: true,
+            ..VmConfig::default()
+        });
+        assert_eq!(
+            vm.run_report().result,
+            RunResult::Error(VmError::BadTrap(0x2C))
+        );
+        assert_eq!(vm.get_rpc(), 0x3001);
+
+        // Every extension trap is rejected, even with a routine in the table
+        for vector in [0x26, 0x27, 0x28, 0x2D, 0x2E, 0x2F] {
+            let mut vm = VM::<&[u8], Vec<u8>>::default();
+            load_program(&mut vm, &[0x3000, 0xF000 | vector, 0xF025]);
+            vm.memory.mem[vector as usize] = 0x4000;
+            vm.set_config(VmConfig {
+                strict: true,
+                ..VmConfig::default()
+            });
+            assert_eq!(
+                vm.run_report().result,
+                RunResult::Error(VmError::BadTrap(vector as u8))
+            );
+            assert!(vm.writer.is_empty());
+        }
+
+        // An unmatched vector with an empty table entry, strict or not
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(&mut vm, &[0x3000, 0b1111_0000_10011001, 0xF025]); // trap x99
+        assert_eq!(
+            vm.run_report().result,
+            RunResult::Error(VmError::BadTrap(0x99))
+        );
+        assert_eq!(vm.get_rpc(), 0x3001);
+    }
+
+    #[test]
+    fn test_jsr_return_address() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(&mut vm, SUBROUTINE_PROGRAM);
+
+        vm.step(); // jsr SUB
+        assert_eq!(vm.reg(Reg::RPC), 0x3003);
+        assert_eq!(vm.reg(Reg::R7), 0x3001);
+
+        vm.step();
+        vm.step();
+        vm.step(); // ret
+        assert_eq!(vm.reg(Reg::RPC), 0x3001);
+
+        // JSRR through R2, saving the address after it too
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(
+            &mut vm,
+            &[
+                0x3000,
+                0b0100_0_00_010_000000, // jsrr r2
+                0b1111000000100101,     // halt
+                0b1100_000_111_000000,  // ret
+            ],
+        );
+        vm.set_reg(Reg::R2, 0x3002);
+        vm.step();
+        assert_eq!(vm.reg(Reg::R7), 0x3001);
+        vm.step();
+        assert_eq!(vm.reg(Reg::RPC), 0x3001);
+        assert_eq!(vm.step(), StepResult::Halted);
+    }
+
+    #[test]
+    fn test_pc_relative_offsets() {
+        // Every offset is added to the address of the next instruction
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(
+            &mut vm,
+            &[
+                0x3000,
+                0b0010_001_000000101,  // ld r1, #5      -> x3006
+                0b1010_010_000000101,  // ldi r2, #5     -> x3007
+                0b1110_011_111111101,  // lea r3, #-3    -> x3000
+                0b0011_001_000000101,  // st r1, #5      -> x3009
+                0b1011_001_000000101,  // sti r1, #5     -> x300A
+                0b0000_111_000000110,  // brnzp #6       -> x300C
+                0x1111,                // x3006
+                0x3008,                // x3007
+                0x2222,                // x3008
+                0x0000,                // x3009
+                0x300B,                // x300A
+                0x0000,                // x300B
+                0b0100_1_00000000001,  // jsr #1         -> x300E
+                0b1111000000100101,    // halt
+                0b1100_000_111_000000, // ret
+            ],
+        );
+
+        vm.step();
+        assert_eq!(vm.reg(Reg::R1), 0x1111);
+        vm.step();
+        assert_eq!(vm.reg(Reg::R2), 0x2222);
+        vm.step();
+        assert_eq!(vm.reg(Reg::R3), 0x3000);
+        vm.step();
+        assert_eq!(vm.memory.read(0x3009), 0x1111);
+        vm.step();
+        assert_eq!(vm.memory.read(0x300B), 0x1111);
+        vm.step();
+        assert_eq!(vm.reg(Reg::RPC), 0x300C);
+        vm.step();
+        assert_eq!(vm.reg(Reg::RPC), 0x300E);
+        assert_eq!(vm.reg(Reg::R7), 0x300D);
+        vm.step();
+        assert_eq!(vm.step(), StepResult::Halted);
+    }
+
+    #[test]
+    fn test_vm_builder() {
+        let table = (0..4).map(|i| (0x4000 + i, 10 * (i + 1)));
+        let mut vm = VmBuilder::new()
+            .preload(table)
+            .preload([
+                (0x3000, 0b0010_001_000000011),  // ld r1 with the table address
+                (0x3001, 0b0110_000_001_000010), // ldr r0 from the table + 2
+                (0x3002, 0b1111000000100101),    // halt
+                (0x3004, 0x4000),
+            ])
+            .config(VmConfig {
+                strict: true,
+                ..VmConfig::default()
+            })
+            .build(&b""[..], Vec::new());
+
+        assert!(vm.config().strict);
+        vm.run();
+        assert_eq!(vm.reg(Reg::R0), 30);
+    }
+
+    #[test]
+    fn test_step_info() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(&mut vm, SUBROUTINE_PROGRAM);
+
+        assert_eq!(
+            vm.step_info(),
+            StepInfo {
+                pc: 0x3000,
+                raw: 0b0100_1_00000000010,
+                decoded: String::from("JSR #2"),
+                result: StepResult::Continue,
+                halted: false,
+            }
+        );
+
+        vm.run();
+        let info = vm.step_info();
+        assert_eq!(info.result, StepResult::Halted);
+        assert!(info.halted);
+    }
+
+    #[test]
+    fn test_break_on_opcode() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(&mut vm, SUBROUTINE_PROGRAM);
+
+        vm.break_on_opcode(0b1111);
+        assert_eq!(
+            vm.resume(),
+            RunResult::OpcodeBreak {
+                pc: 0x3002,
+                opcode: 0b1111
+            }
+        );
+        assert_eq!(vm.reg(Reg::R0), 6);
+        assert_eq!(vm.resume(), RunResult::Halted);
+
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(&mut vm, SUBROUTINE_PROGRAM);
+        vm.break_on_opcode(0b0001);
+        vm.add_breakpoint(0x3003);
+        assert_eq!(vm.resume(), RunResult::Breakpoint { pc: 0x3003 });
+        assert_eq!(
+            vm.resume(),
+            RunResult::OpcodeBreak {
+                pc: 0x3004,
+                opcode: 0b0001
+            }
+        );
+        vm.remove_opcode_break(0b0001);
+        assert_eq!(vm.resume(), RunResult::Halted);
+    }
+
+    #[test]
+    fn test_step_out() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(&mut vm, SUBROUTINE_PROGRAM);
+
+        assert_eq!(vm.step(), StepResult::Continue);
+        assert_eq!(vm.reg(Reg::RPC), 0x3003);
+        assert_eq!(vm.step(), StepResult::Continue);
+
+        assert_eq!(vm.step_out(), RunResult::Returned);
+        assert_eq!(vm.reg(Reg::RPC), 0x3001);
+        assert_eq!(vm.reg(Reg::R0), 6);
+        assert_eq!(vm.reg(Reg::R1), 0);
+
+        assert_eq!(vm.step_out(), RunResult::Halted);
+        assert_eq!(vm.reg(Reg::R1), 1);
+        assert_eq!(vm.step(), StepResult::Halted);
+    }
+
+    #[test]
+    fn test_call_subroutine() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(
+            &mut vm,
+            &[
+                0x4000,
+                0b0001_000_000_0_00_000, // DOUBLE: add r0 and r0 in r0
+                0b1100_000_111_000000,   // ret
+                0b0000_111_111111111,    // LOOP: br LOOP
+            ],
+        );
+
+        vm.set_reg(Reg::R0, 21);
+        assert_eq!(vm.call_subroutine(0x4000, 100), RunResult::Returned);
+        assert_eq!(vm.reg(Reg::R0), 42);
+        assert_eq!(vm.get_rpc(), SUBROUTINE_SENTINEL);
+
+        assert_eq!(vm.call_subroutine(0x4002, 100), RunResult::StepLimit);
+        assert_eq!(vm.get_rpc(), 0x4002);
+    }
+
+    #[test]
+    fn test_debug_step_traps() {
+        let program = &[
+            0x3000,
+            0b1111_0000_00110000,   // trap x30, installed at 0x3003
+            0b0001_001_001_1_00001, // add r1 and 1 in r1
+            0b1111000000100101,     // halt
+            0b0001_000_000_1_00001, // x3003: add r0 and 1 in r0
+            0b0001_000_000_1_00001, // add r0 and 1 in r0
+            0b1100_000_111_000000,  // ret
+        ];
+
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(&mut vm, program);
+        vm.memory.mem[0x30] = 0x3003;
+        assert_eq!(vm.debug_step(), StepResult::Trap(0x30));
+        assert_eq!(vm.get_rpc(), 0x3001);
+        assert_eq!(vm.reg(Reg::R0), 2);
+        assert!(vm.breakpoints().is_empty());
+
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(&mut vm, program);
+        vm.memory.mem[0x30] = 0x3003;
+        vm.set_config(VmConfig {
+            step_into_traps: true,
+            ..VmConfig::default()
+        });
+        assert_eq!(vm.debug_step(), StepResult::Trap(0x30));
+        assert_eq!(vm.get_rpc(), 0x3003);
+        assert_eq!(vm.reg(Reg::R0), 0);
+
+        // A breakpoint in the routine stops stepping over it
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(&mut vm, program);
+        vm.memory.mem[0x30] = 0x3003;
+        vm.add_breakpoint(0x3004);
+        assert_eq!(
+            vm.debug_step(),
+            StepResult::Fault(RunResult::Breakpoint { pc: 0x3004 })
+        );
+        assert_eq!(vm.breakpoints(), &HashSet::from([0x3004]));
+    }
+
+    #[test]
+    fn test_step_out_breakpoint() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(&mut vm, SUBROUTINE_PROGRAM);
+        vm.add_breakpoint(0x3004);
+
+        vm.step();
+        assert_eq!(vm.step_out(), RunResult::Breakpoint { pc: 0x3004 });
+        assert_eq!(vm.reg(Reg::R0), 5);
+
+        assert_eq!(vm.step_out(), RunResult::Returned);
+        assert_eq!(vm.reg(Reg::RPC), 0x3001);
+    }
+
+    #[test]
+    fn test_load_odd_length() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+
+        let result = vm.load(&[0x30, 0x00, 0xF0, 0x25, 0x12][..]);
+
+        assert_eq!(result, Err(LoadError::OddLength));
+        assert_eq!(vm.memory.read(0x3000), 0xF025);
+    }
+
+    /// Yields `bytes` then fails
+    struct BrokenImage<'a> {
+        bytes: &'a [u8],
+    }
+
+    impl Read for BrokenImage<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.bytes.is_empty() {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated"));
+            }
+            self.bytes.read(buf)
+        }
+    }
+
+    #[test]
+    fn test_load_result() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        assert_eq!(vm.load(&[0x40, 0x00, 0xF0, 0x25][..]), Ok(0x4000));
+        assert_eq!(vm.reg(Reg::RPC), 0x4000);
+
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        assert_eq!(vm.load(&[][..]), Err(LoadError::EmptyImage));
+        assert_eq!(vm.reg(Reg::RPC), PC_START as u16);
+
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        assert_eq!(vm.load(&[0x30, 0x00, 0xF0][..]), Err(LoadError::OddLength));
+
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        let image = BrokenImage {
+            bytes: &[0x30, 0x00, 0x12, 0x61],
+        };
+        assert_eq!(
+            vm.load(image),
+            Err(LoadError::Io(io::ErrorKind::UnexpectedEof))
+        );
+        assert_eq!(vm.memory.read(0x3000), 0x1261);
+    }
+
+    #[test]
+    fn test_load_wraps_around() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+
+        load_program(&mut vm, &[0xFFFF, 0b0001_001_001_1_00001, 0xF025]);
+
+        assert_eq!(vm.memory.read(0xFFFF), 0b0001_001_001_1_00001);
+        assert_eq!(vm.memory.read(0x0000), 0xF025);
+        vm.step();
+        assert_eq!(vm.reg(Reg::R1), 1);
+        assert_eq!(vm.reg(Reg::RPC), 0x0000);
+        assert_eq!(vm.step(), StepResult::Halted);
+
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.load_words(0xFFFF, &[0x1234, 0x5678]).unwrap();
+        assert_eq!(vm.memory.read(0x0000), 0x5678);
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.set_config(VmConfig {
+            strict: true,
+            ..VmConfig::default()
+        });
+        assert_eq!(
+            vm.load(&words_to_obj(0xFFFE, &[0x1234, 0x5678, 0x9ABC])[..]),
+            Err(LoadError::ImageTooLarge { origin: 0xFFFE })
+        );
+        assert_eq!(vm.memory.read(0x0000), 0);
+        assert_eq!(
+            vm.load(&words_to_obj(0xFFFE, &[0x1234, 0x5678])[..]),
+            Ok(0xFFFE)
+        );
+
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.set_config(VmConfig {
+            strict: true,
+            ..VmConfig::default()
+        });
+        assert_eq!(
+            vm.load(&words_to_obj(0xFFF0, &[0xF025; 16])[..]),
+            Ok(0xFFF0)
+        );
+        assert_eq!(vm.set_entry(0xFFF0), Ok(()));
+        assert_eq!(vm.set_entry(0xFFFF), Ok(()));
+        assert_eq!(vm.set_entry(0x0000), Err(EntryOutsideImage(0x0000)));
+        assert_eq!(vm.set_entry(0xFFEF), Err(EntryOutsideImage(0xFFEF)));
+        assert_eq!(vm.classify_address(0xFFFF), AddrKind::Code);
+        assert_eq!(vm.classify_address(0x0000), AddrKind::Unknown);
+
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.load_words(0xFFFF, &[0xF025, 0xF025]).unwrap();
+        vm.set_config(VmConfig {
+            strict: true,
+            ..VmConfig::default()
+        });
+        assert_eq!(vm.set_entry(0x0000), Ok(()));
+        assert_eq!(vm.set_entry(0xFFFF), Ok(()));
+        assert_eq!(vm.set_entry(0x0001), Err(EntryOutsideImage(0x0001)));
+        assert_eq!(vm.classify_address(0xFFFF), AddrKind::Code);
+        assert_eq!(vm.classify_address(0x0000), AddrKind::Code);
+        assert_eq!(vm.classify_address(0x0001), AddrKind::Unknown);
+        let mut listing = Vec::new();
+        vm.write_listing(&mut listing).unwrap();
+        assert_eq!(String::from_utf8(listing).unwrap().lines().count(), 2);
+    }
+
+    #[test]
+    fn test_parse_and_commit_image() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+
+        let result = parse_image(&[0x30, 0x00, 0xF0, 0x25, 0x12][..]);
+        assert_eq!(result, Err(LoadError::OddLength));
+        assert_eq!(vm.memory.read(0x3000), 0);
+
+        let image = parse_image(&[0x40, 0x00, 0x12, 0x61, 0xF0, 0x25][..]).unwrap();
+        assert_eq!(
+            image,
+            LoadedImage {
+                origin: 0x4000,
+                words: vec![0x1261, 0xF025]
+            }
+        );
+        assert_eq!(vm.memory.read(0x4000), 0);
+
+        assert_eq!(vm.commit_image(&image), Ok(()));
+        assert_eq!(vm.memory.read(0x4000), 0x1261);
+        assert_eq!(vm.memory.read(0x4001), 0xF025);
+        assert_eq!(vm.reg(Reg::RPC), 0x4000);
+        vm.run();
+        assert_eq!(vm.reg(Reg::R1), 1);
+    }
+
+    #[test]
+    fn test_load_words() {
+        let assembled = assembler::assemble(
+            r#"
+        .ORIG x4000
+        ADD R0, R0, #7
+        TRAP x25
+        .END
+"#,
+        )
+        .unwrap();
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.load_words(assembled.origin, &assembled.words).unwrap();
+
+        assert_eq!(vm.get_rpc(), 0x4000);
+        assert_eq!(vm.run(), 2);
+        assert_eq!(vm.reg(Reg::R0), 7);
+    }
+
+    #[test]
+    fn test_load_expecting_origin() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        let obj = words_to_obj(0x4000, &[0x1261, 0xF025]);
+
+        assert_eq!(
+            vm.load_expecting_origin(&obj[..], 0x3000),
+            Err(LoadError::OriginMismatch {
+                found: 0x4000,
+                expected: 0x3000
+            })
+        );
+        assert_eq!(vm.memory.read(0x4000), 0);
+        assert_eq!(vm.reg(Reg::RPC), 0x3000);
+
+        assert_eq!(vm.load_expecting_origin(&obj[..], 0x4000), Ok(()));
+        assert_eq!(vm.memory.read(0x4000), 0x1261);
+        assert_eq!(vm.reg(Reg::RPC), 0x4000);
+
+        assert_eq!(
+            vm.load_expecting_origin(&[][..], 0),
+            Err(LoadError::EmptyImage)
+        );
+
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.set_config(VmConfig {
+            strict: true,
+            ..VmConfig::default()
+        });
+        let obj = words_to_obj(0xFFFF, &[0x1261, 0xF025]);
+        assert_eq!(
+            vm.load_expecting_origin(&obj[..], 0xFFFF),
+            Err(LoadError::ImageTooLarge { origin: 0xFFFF })
+        );
+        assert_eq!(
+            vm.load_words(0xFFFF, &[0x1261, 0xF025]),
+            Err(LoadError::ImageTooLarge { origin: 0xFFFF })
+        );
+        assert_eq!(vm.memory.read(0xFFFF), 0);
+        assert_eq!(vm.memory.read(0x0000), 0);
+        assert_eq!(vm.load_words(0xFFFF, &[0xF025]), Ok(()));
+    }
+
+    #[test]
+    fn test_obj_words_roundtrip() {
+        let words = [0x1261, 0xF025, 0x0000, 0xFFFF];
+        let obj = words_to_obj(0x3000, &words);
+        assert_eq!(obj[..4], [0x30, 0x00, 0x12, 0x61]);
+        assert_eq!(obj.len(), 10);
+
+        assert_eq!(obj_to_words(&obj), Ok((0x3000, words.to_vec())));
+        assert_eq!(obj_to_words(&obj[..9]), Err(LoadError::OddLength));
+        assert_eq!(obj_to_words(&[]), Err(LoadError::EmptyImage));
+    }
+
+    #[test]
+    fn test_timer_instructions() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.memory.write(MR_TMI, 5);
+
+        for _ in 0..4 {
+            vm.step(); // 0x0000 never branches
+        }
+        assert_eq!(vm.memory.read_device(MR_TMR), 0);
+
+        vm.step();
+        assert_eq!(vm.memory.read_device(MR_TMR), 1 << 15);
+        assert_eq!(vm.memory.read_device(MR_TMR), 0);
+
+        for _ in 0..5 {
+            vm.step();
+        }
+        assert_eq!(vm.memory.read_device(MR_TMR), 1 << 15);
+    }
+
+    #[test]
+    fn test_memory_read_is_pure() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.memory.write(MR_TMI, 1);
+        vm.step();
+
+        assert_eq!(vm.memory.read(MR_TMR), 0);
+        assert_eq!(vm.memory.read(MR_TMR), 0);
+        assert_eq!(vm.memory.read_device(MR_TMR), 1 << 15);
+        assert_eq!(vm.memory.read(MR_TMR), 1 << 15);
+    }
 
     #[test]
-    fn test_load_and_run() {
+    fn test_protect_zero_page() {
+        let program = &[
+            0x3000,                 // start = 0x3000
+            0b0001_000_000_1_01000, // add r0 and 8 in r0
+            0b0001_000_000_1_01000, // add r0 and 8 in r0
+            0b0001_001_001_1_00111, // add r1 and 7 in r1
+            0b0111_001_000_000000,  // str r1 at r0/0x0010
+            0b1111000000100101,     // halt
+        ];
+
         let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(&mut vm, program);
+        vm.set_config(VmConfig {
+            protect_zero_page: true,
+            ..VmConfig::default()
+        });
 
-        let program: &[u16] = &[
-            0x3000,             // start = 0x3000; // 00110000 00000000
-            0b0001001001100011, // add r1/0 and 3 in r1/3
-            0b0001010010100100, // add r2/0 and 4 in r2/4
-            0b0001000001000010, // add r1/3 and r2/4 in r0/7
-            0b0101001001100001, // and r1/3 and 1 in r1/1
-            0b0101111000000010, // and r0/7 and r2/4 in r7/4
-            0b0010101000000011, // ld offset 3 DATA/718 in r5/718
-            0b1111000000100101, // halt
+        assert_eq!(vm.resume(), RunResult::ProtectionViolation { addr: 0x0010 });
+        assert_eq!(vm.memory.read(0x0010), 0);
+        assert_eq!(vm.reg(Reg::RPC), 0x3004);
+        assert_eq!(vm.resume(), RunResult::Halted);
+
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(&mut vm, program);
+        assert_eq!(vm.resume(), RunResult::Halted);
+        assert_eq!(vm.memory.read(0x0010), 7);
+    }
+
+    #[test]
+    fn test_breakpoints_and_watchpoints() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(
+            &mut vm,
+            &[
+                0x3000,
+                0b0001_000_000_1_00111, // add r0 and 7 in r0
+                0b0011_000_000000001,   // st r0 at 0x3003
+                0b1111000000100101,     // halt
+                0x0000,                 // data
+            ],
+        );
+
+        vm.add_breakpoint(0x3001);
+        vm.add_breakpoint(0x3002);
+        vm.add_watchpoint(0x3003);
+        vm.add_watchpoint(0x4000);
+        assert_eq!(vm.breakpoints(), &HashSet::from([0x3001, 0x3002]));
+        assert_eq!(vm.watchpoints(), &HashSet::from([0x3003, 0x4000]));
+
+        vm.clear_breakpoints();
+        assert!(vm.breakpoints().is_empty());
+        assert_eq!(vm.resume(), RunResult::Watchpoint { addr: 0x3003 });
+        assert_eq!(vm.memory.read(0x3003), 7);
+        assert_eq!(vm.reg(Reg::RPC), 0x3002);
+
+        vm.clear_watchpoints();
+        assert!(vm.watchpoints().is_empty());
+        assert_eq!(vm.resume(), RunResult::Halted);
+    }
+
+    #[test]
+    fn test_hot_addresses() {
+        let program = &[
+            0x3000,
+            0b0001_001_001_1_00101, // add r1 and 5 in r1
+            0b0001_000_000_1_00010, // LOOP: add r0 and 2 in r0
+            0b0001_001_001_1_11111, // add r1 and -1 in r1
+            0b0000_001_111111101,   // brp LOOP
+            0b1111000000100101,     // halt
+        ];
+
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(&mut vm, program);
+        vm.run();
+        assert!(vm.hot_addresses(3).is_empty());
+
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(&mut vm, program);
+        vm.set_config(VmConfig {
+            profile_addresses: true,
+            ..VmConfig::default()
+        });
+        vm.run();
+        assert_eq!(vm.reg(Reg::R0), 10);
+        assert_eq!(
+            vm.hot_addresses(3),
+            vec![(0x3001, 5), (0x3002, 5), (0x3003, 5)]
+        );
+        assert_eq!(vm.hot_addresses(10).len(), 5);
+        assert_eq!(vm.hot_addresses(10)[4], (0x3004, 1));
+    }
+
+    #[test]
+    fn test_data_execution() {
+        let assembled = assembler::assemble(
+            r#"
+        .ORIG x3000
+        ADD R0, R0, #1
+        BR DATA
+        TRAP x25
+DATA    .FILL x1021
+        TRAP x25
+        .END
+"#,
+        )
+        .unwrap();
+        let image = assembled.to_bytes();
+
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.load(&image[..]).unwrap();
+        vm.mark_data(assembled.data.iter().copied());
+        assert_eq!(vm.resume(), RunResult::Halted);
+        assert_eq!(vm.reg(Reg::R0), 2);
+
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.load(&image[..]).unwrap();
+        vm.mark_data(assembled.data.iter().copied());
+        vm.set_config(VmConfig {
+            strict_execution: true,
+            ..VmConfig::default()
+        });
+        assert_eq!(vm.resume(), RunResult::DataExecution { addr: 0x3003 });
+        assert_eq!(vm.reg(Reg::R0), 1);
+        assert_eq!(vm.reg(Reg::RPC), 0x3003);
+    }
+
+    #[cfg(feature = "extended-registers")]
+    #[test]
+    fn test_extended_registers() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        assert_eq!(vm.reg(Reg::R15), 0);
+
+        vm.set_reg(Reg::from(9), 0x1234);
+        assert_eq!(vm.reg(Reg::R9), 0x1234);
+
+        // The instruction fields still select R0-R7
+        assert_eq!(Reg::dr(0b0001_111_000_1_00001), Reg::R7);
+
+        assert!(vm
+            .dump_registers()
+            .contains("R7=x0000 R8=x0000 R9=x1234 R10=x0000"));
+        assert!(vm.dump_registers().contains("R15=x0000 PC=x3000"));
+    }
+
+    #[test]
+    fn test_instruction_count_trap() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(
+            &mut vm,
+            &[
+                0x3000,
+                0b0001_001_001_1_00001, // add r1 and 1 in r1
+                0b0001_001_001_1_00001, // add r1 and 1 in r1
+                0b0001_001_001_1_00001, // add r1 and 1 in r1
+                0b1111000000101101,     // read the instruction count
+                0b1111000000100101,     // halt
+            ],
+        );
+
+        assert_eq!(vm.run(), 5);
+        assert_eq!(vm.reg(Reg::R0), 4);
+        assert_eq!(vm.instruction_count(), 5);
+    }
+
+    #[test]
+    fn test_classify_address() {
+        let assembled = assembler::assemble(
+            r#"
+        .ORIG x3000
+        LD R0, VALUE
+        TRAP x25
+VALUE   .FILL x0000
+        .FILL x1021
+        .END
+"#,
+        )
+        .unwrap();
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.load_assembled(&assembled).unwrap();
+        assert_eq!(vm.classify_address(0x3000), AddrKind::Code);
+        assert_eq!(vm.classify_address(0x3001), AddrKind::Code);
+        assert_eq!(vm.classify_address(0x3002), AddrKind::Data);
+        assert_eq!(vm.classify_address(0x3003), AddrKind::Data);
+        assert_eq!(vm.classify_address(0x3004), AddrKind::Unknown);
+
+        // Without the assembler information the valid instructions are taken as code
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.load(&assembled.to_bytes()[..]).unwrap();
+        assert_eq!(vm.classify_address(0x3000), AddrKind::Code);
+        assert_eq!(vm.classify_address(0x3002), AddrKind::Data);
+        assert_eq!(vm.classify_address(0x3003), AddrKind::Code);
+        assert_eq!(vm.classify_address(0x2FFF), AddrKind::Unknown);
+    }
+
+    #[test]
+    fn test_patch() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(&mut vm, SUBROUTINE_PROGRAM);
+
+        // Nop out the add of 5, a BR without condition never branches
+        assert_eq!(vm.patch(0x3003, 0x0000), ".FILL x0000");
+        assert_eq!(vm.patch(0x3003, 0x0E00), "BRnzp #0");
+        assert_eq!(vm.memory.mem[0x3003], 0x0E00);
+        assert_eq!(
+            vm.patches(),
+            &HashMap::from([(0x3003, 0b0001_000_000_1_00101)])
+        );
+
+        vm.run();
+        assert_eq!(vm.reg(Reg::R0), 1);
+    }
+
+    #[test]
+    fn test_memory_map() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(&mut vm, SUBROUTINE_PROGRAM);
+        vm.memory.mem[0x4000] = 0x1234;
+
+        assert_eq!(
+            vm.memory_map(),
+            vec![
+                (0x0000..=0x2FFF, RegionKind::Untouched),
+                (0x3000..=0x3005, RegionKind::Code),
+                (0x3006..=0x3FFF, RegionKind::Untouched),
+                (0x4000..=0x4000, RegionKind::Written),
+                (0x4001..=0xFDFF, RegionKind::Untouched),
+                (0xFE00..=0xFFFF, RegionKind::Device),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_write_listing() {
+        let assembled = assembler::assemble(
+            r#"
+        .ORIG x3000
+MAIN    JSR SUB
+        ADD R1, R1, #1
+        TRAP x25
+SUB     ADD R0, R0, #5
+        JMP R7
+VALUE   .FILL x1021
+        .END
+"#,
+        )
+        .unwrap();
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.load_assembled(&assembled).unwrap();
+        vm.set_config(VmConfig {
+            profile_addresses: true,
+            ..VmConfig::default()
+        });
+        vm.run();
+
+        let mut listing = Vec::new();
+        vm.write_listing(&mut listing).unwrap();
+        let listing = String::from_utf8(listing).unwrap();
+        let lines: Vec<&str> = listing.lines().collect();
+
+        assert_eq!(lines.len(), 6);
+        assert_eq!(
+            lines[0],
+            "x3000  x4802  MAIN     JSR #2  ; -> x3003 SUB  [1]"
+        );
+        assert_eq!(lines[1], "x3001  x1261           ADD R1, R1, #1  [1]");
+        assert_eq!(lines[3], "x3003  x1025  SUB      ADD R0, R0, #5  [1]");
+        assert_eq!(lines[5], "x3005  x1021  VALUE    .FILL x1021");
+    }
+
+    /// Fails every read, like a closed pipe
+    struct FailingReader;
+
+    impl Read for FailingReader {
+        fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+            Err(io::Error::new(io::ErrorKind::BrokenPipe, "closed"))
+        }
+    }
+
+    #[test]
+    fn test_new() {
+        let mut vm = VM::new(io::Cursor::new(b"ok".to_vec()), Vec::new());
+        vm.load_words(0x3000, &[0xF020, 0xF021, 0xF020, 0xF021, 0xF025])
+            .unwrap(); // getc out getc out halt
+        assert_eq!(vm.run(), 5);
+        assert_eq!(vm.writer, b"ok");
+    }
+
+    #[test]
+    fn test_keyboard() {
+        let program = &[
+            0x3000,
+            0b1010_000_000000010, // ldi r0 from KBSR
+            0b1010_001_000000010, // ldi r1 from KBDR
+            0b1111000000100101,   // halt
+            MR_KBSR,
+            MR_KBDR,
+        ];
+
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(&mut vm, program);
+        vm.set_reader(b"a");
+        assert_eq!(vm.resume(), RunResult::Halted);
+        assert_eq!(vm.reg(Reg::R0), 1 << 15);
+        assert_eq!(vm.reg(Reg::R1), b'a' as u16);
+
+        let mut vm = VM::new(FailingReader, Vec::new());
+        let bytes: Vec<u8> = program.iter().flat_map(|w| w.to_be_bytes()).collect();
+        vm.load(bytes.as_slice()).unwrap();
+        assert_eq!(
+            vm.resume(),
+            RunResult::Error(VmError::Io(io::ErrorKind::BrokenPipe))
+        );
+        assert_eq!(vm.reg(Reg::R0), 0);
+    }
+
+    /// Fails every write, or only the flushes, like a closed stdout pipe
+    struct FailingWriter {
+        flush_only: bool,
+    }
+
+    impl Write for FailingWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.flush_only {
+                Ok(buf.len())
+            } else {
+                Err(io::Error::new(io::ErrorKind::BrokenPipe, "closed"))
+            }
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Err(io::Error::new(io::ErrorKind::BrokenPipe, "closed"))
+        }
+    }
+
+    #[test]
+    fn test_trap_writer_error() {
+        // out, puts, the flush trap
+        for trap in [0xF021, 0xF022, 0xF02F] {
+            for flush_only in [false, true] {
+                let mut vm = VM::new(&b""[..], FailingWriter { flush_only });
+                vm.load_words(0x3000, &[trap, 0xF025]).unwrap();
+                vm.write_memory(0x4000, b'a' as u16);
+                vm.set_reg(Reg::R0, 0x4000);
+                assert_eq!(
+                    vm.resume(),
+                    RunResult::Error(VmError::Io(io::ErrorKind::BrokenPipe))
+                );
+                assert_eq!(vm.reg(Reg::RPC), 0x3001);
+            }
+        }
+    }
+
+    #[test]
+    fn test_trap_reader_error() {
+        // getc, in, the u16 and i16 input traps
+        for trap in [0xF020, 0xF023, 0xF026, 0xF02E] {
+            let mut vm = VM::new(FailingReader, Vec::new());
+            vm.load_words(0x3000, &[trap, 0xF025]).unwrap();
+            vm.set_reg(Reg::R0, 0x1234);
+            assert_eq!(
+                vm.resume(),
+                RunResult::Error(VmError::Io(io::ErrorKind::BrokenPipe))
+            );
+            assert_eq!(vm.reg(Reg::R0), 0x1234);
+            assert_eq!(vm.reg(Reg::RPC), 0x3001);
+        }
+    }
+
+    #[test]
+    fn test_read_write_memory() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.set_reader(b"a");
+
+        vm.write_memory(0x4000, 0xBEEF);
+        assert_eq!(vm.read_memory(0x4000), Ok(0xBEEF));
+        assert_eq!(vm.read_memory(MR_KBDR), Ok(0)); // no polling, the key is still pending
+
+        assert_eq!(vm.read_memory(MR_KBSR), Ok(1 << 15));
+        assert_eq!(vm.read_memory(MR_KBDR), Ok(b'a' as u16));
+        assert_eq!(vm.read_memory(MR_KBSR), Ok(0));
+
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(&mut vm, &[0x3000, 0b0001_000_000_1_00001, 0xF025]); // add r0, r0, #1
+        vm.set_config(VmConfig {
+            bus_mode: BusMode::Strict,
+            ..VmConfig::default()
+        });
+        assert_eq!(vm.read_memory(0xFE20), Err(VmError::BusError(0xFE20)));
+        assert_eq!(vm.step(), StepResult::Continue);
+        assert_eq!(vm.reg(Reg::R0), 1);
+
+        let mut vm = VM::new(FailingReader, Vec::new());
+        assert_eq!(
+            vm.read_memory(MR_KBSR),
+            Err(VmError::Io(io::ErrorKind::BrokenPipe))
+        );
+        vm.load_words(0x3000, &[0xF025]).unwrap();
+        assert_eq!(vm.step(), StepResult::Halted);
+    }
+
+    #[test]
+    fn test_compat_reference() {
+        let program = &[
+            0x3000,
+            0b1111000000100000,     // getc
+            0b0000_001_000000001,   // brp over the next add
+            0b0001_010_010_1_00001, // add r2 and 1 in r2
+            0b1111000000110000,     // trap x30, not installed
+            0b1111000000100101,     // halt
+        ];
+
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(&mut vm, program);
+        vm.set_reader(b"a");
+        assert_eq!(vm.resume(), RunResult::Error(VmError::BadTrap(0x30)));
+        assert_eq!(vm.reg(Reg::R2), 1);
+
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(&mut vm, program);
+        vm.set_reader(b"a");
+        vm.set_config(VmConfig {
+            compat_reference: true,
+            ..VmConfig::default()
+        });
+        assert_eq!(vm.resume(), RunResult::Halted);
+        assert_eq!(vm.reg(Reg::R2), 0);
+        assert_eq!(vm.reg(Reg::R7), 0x3005);
+        assert_eq!(vm.writer, b"HALT\n");
+    }
+
+    #[test]
+    fn test_find_references() {
+        let assembled = assembler::assemble(
+            r#"
+        .ORIG x3000
+        JSR SUB
+        LEA R1, SUB
+        BRz DONE
+        JSR SUB
+DONE    TRAP x25
+SUB     JMP R7
+PTR     .FILL SUB
+        .END
+"#,
+        )
+        .unwrap();
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.load_assembled(&assembled).unwrap();
+
+        assert_eq!(
+            vm.find_references(0x3005),
+            vec![0x3000, 0x3001, 0x3003, 0x3006]
+        );
+        assert_eq!(vm.find_references(0x3004), vec![0x3002]);
+        assert!(vm.find_references(0x3000).is_empty());
+    }
+
+    #[test]
+    fn test_stack_range() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(
+            &mut vm,
+            &[
+                0x3000,
+                0b1110_110_000000101,   // lea r6 with the stack top 0x3006
+                0b0001_110_110_1_11111, // push: add r6 and -1 in r6
+                0b0001_110_110_1_00001, // pop: add r6 and 1 in r6
+                0b0001_110_110_1_00001, // pop again, out of the stack
+                0b0001_000_000_1_00001, // add r0 and 1 in r0
+                0b1111000000100101,     // halt
+            ],
+        );
+        vm.set_reg(Reg::R6, 0x3005);
+        vm.set_stack_range(0x3004..0x3007);
+
+        let events = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let hook_events = events.clone();
+        vm.set_trace_hook(Box::new(move |e| hook_events.lock().unwrap().push(e)));
+
+        assert_eq!(vm.resume(), RunResult::Halted);
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![TraceEvent::StackOutOfRange {
+                pc: 0x3003,
+                sp: 0x3007
+            }]
+        );
+    }
+
+    #[test]
+    fn test_run_with_timeout() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(
+            &mut vm,
+            &[
+                0x3000,
+                0b0000_111_111111111, // LOOP: br LOOP
+            ],
+        );
+
+        let start = Instant::now();
+        assert_eq!(
+            vm.run_with_timeout(Duration::from_millis(50)),
+            RunResult::Timeout
+        );
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(&mut vm, SUBROUTINE_PROGRAM);
+        assert_eq!(
+            vm.run_with_timeout(Duration::from_secs(5)),
+            RunResult::Halted
+        );
+    }
+
+    #[test]
+    fn test_clock_hz() {
+        let countdown = |vm: &mut VM<&[u8], Vec<u8>>| {
+            load_program(
+                vm,
+                &[
+                    0x3000,
+                    0b0010_000_000000011,   // ld r0 from 0x3004
+                    0b0001_000_000_1_11111, // LOOP: add r0 and -1 in r0
+                    0b0000_001_111111110,   // brp LOOP
+                    0b1111000000100101,     // halt
+                    1500,
+                ],
+            );
+        };
+
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        countdown(&mut vm);
+        vm.set_clock_hz(10_000);
+        let start = Instant::now();
+        assert_eq!(vm.run(), 3002);
+        assert!(start.elapsed() >= Duration::from_millis(250));
+
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        countdown(&mut vm);
+        vm.set_clock_hz(10_000);
+        vm.set_clock_hz(0);
+        let start = Instant::now();
+        vm.run();
+        assert!(start.elapsed() < Duration::from_millis(250));
+    }
+
+    #[test]
+    fn test_schedule_interrupt() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(
+            &mut vm,
+            &[
+                0x3000,
+                0b0001_001_001_1_00001, // add r1 and 1 in r1
+                0b0001_001_001_1_00001, // add r1 and 1 in r1
+                0b0001_001_001_1_00001, // add r1 and 1 in r1
+                0b1111000000100101,     // halt
+            ],
+        );
+        load_program(
+            &mut vm,
+            &[
+                0x4000,
+                0b0001_000_001_1_00000, // handler: add r1 and 0 in r0
+                0b1000_000000000000,    // rti
+            ],
+        );
+        vm.set_reg(Reg::RPC, 0x3000);
+        vm.set_reg(Reg::R6, 0x2000);
+        vm.memory.mem[0x0180] = 0x4000;
+        vm.set_priority(5).unwrap();
+        assert_eq!(vm.schedule_interrupt(1, 0x80, 9), Err(InvalidPriority(9)));
+        vm.schedule_interrupt(1, 0x80, 4).unwrap();
+        vm.schedule_interrupt(2, 0x80, 6).unwrap();
+
+        // Only the interrupt of priority 6 is above the program's 5
+        vm.step();
+        vm.step();
+        assert_eq!(vm.reg(Reg::R1), 2);
+        vm.step();
+        assert_eq!(vm.reg(Reg::R0), 2);
+        assert_eq!(vm.priority(), 6);
+        assert_eq!(vm.reg(Reg::R6), 0x1FFE);
+
+        vm.step();
+        assert_eq!(vm.get_rpc(), 0x3002);
+        assert_eq!(vm.priority(), 5);
+        assert_eq!(vm.resume(), RunResult::Halted);
+        assert_eq!(vm.reg(Reg::R1), 3);
+    }
+
+    #[test]
+    fn test_interrupt() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(&mut vm, SUBROUTINE_PROGRAM);
+        let flag = Arc::new(AtomicBool::new(false));
+        vm.set_interrupt(flag.clone());
+
+        assert_eq!(vm.step(), StepResult::Continue);
+        flag.store(true, Ordering::Relaxed);
+        assert_eq!(vm.resume(), RunResult::Interrupted { pc: 0x3003 });
+        assert!(!flag.load(Ordering::Relaxed));
+        assert_eq!(vm.get_rpc(), 0x3003);
+
+        assert_eq!(vm.resume(), RunResult::Halted);
+        assert_eq!(vm.reg(Reg::R0), 6);
+        assert_eq!(vm.reg(Reg::R1), 1);
+    }
+
+    #[test]
+    fn test_record_and_replay_input() {
+        let program = &[
+            0x3000,
+            0b1111000000100000,   // getc
+            0b1111000000100001,   // out
+            0b1010_001_000000010, // ldi r1 from KBSR
+            0b1111000000100011,   // in
+            0b1111000000100101,   // halt
+            MR_KBSR,
+        ];
+
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(&mut vm, program);
+        vm.set_reader(b"abcdef");
+        vm.record_input(true);
+        vm.run();
+        assert_eq!(vm.recorded_input(), b"abc");
+
+        let input = vm.recorded_input().to_vec();
+        let mut replayed = VM::replay(&input);
+        load_program(&mut replayed, program);
+        replayed.run();
+        assert_eq!(replayed.writer, vm.writer);
+        assert_eq!(replayed.registers, vm.registers);
+    }
+
+    #[test]
+    fn test_mem_accesses() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(
+            &mut vm,
+            &[
+                0x3000,
+                0b0010_000_000000100,   // ld r0 from 0x3005
+                0b0001_000_000_1_00001, // add r0 and 1 in r0
+                0b0011_000_000000011,   // st r0 at 0x3006
+                0b1010_001_000000011,   // ldi r1 through 0x3007
+                0b1111000000100101,     // halt
+                0x0041,
+                0x0000,
+                0x3006,
+            ],
+        );
+
+        vm.step();
+        assert!(vm.take_mem_accesses().is_empty());
+
+        vm.record_mem_accesses(true);
+        vm.run();
+        assert_eq!(
+            vm.take_mem_accesses(),
+            vec![
+                MemAccess {
+                    pc: 0x3002,
+                    addr: 0x3006,
+                    kind: AccessKind::Write,
+                    value: 0x0042
+                },
+                MemAccess {
+                    pc: 0x3003,
+                    addr: 0x3007,
+                    kind: AccessKind::Read,
+                    value: 0x3006
+                },
+                MemAccess {
+                    pc: 0x3003,
+                    addr: 0x3006,
+                    kind: AccessKind::Read,
+                    value: 0x0042
+                },
+            ]
+        );
+        assert!(vm.take_mem_accesses().is_empty());
+    }
+
+    #[test]
+    fn test_output_sink() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(
+            &mut vm,
+            &[
+                0x3000,
+                0b1110_000_000000010, // lea r0 with the string
+                0b1111000000100010,   // puts
+                0b1111000000100101,   // halt
+                b'H' as u16,
+                b'i' as u16,
+                b'!' as u16,
+                0,
+            ],
+        );
+
+        let received = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink_received = received.clone();
+        vm.set_output_sink(Box::new(move |b| sink_received.lock().unwrap().push(b)));
+        vm.run();
+
+        assert_eq!(*received.lock().unwrap(), b"Hi!");
+        assert_eq!(vm.writer, b"Hi!");
+    }
+
+    #[test]
+    fn test_max_output_bytes() {
+        let program = &[
+            0x3000,
+            0b1110_000_000000010, // LOOP: lea r0 with the string
+            0b1111000000100010,   // puts
+            0b0000_111_111111101, // br LOOP
+            b'H' as u16,
+            b'i' as u16,
             0,
-            0b0000001011001110, // DATA/718
         ];
 
-        let mut res: [u8; 20] = [0; 20];
-        for i in 0..program.len() {
-            res[i * 2] = (program[i] >> 8) as u8;
-            res[i * 2 + 1] = (program[i] & 0x00FF) as u8;
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(&mut vm, program);
+        vm.set_config(VmConfig {
+            max_output_bytes: Some(5),
+            ..VmConfig::default()
+        });
+        for _ in 0..30 {
+            assert!(vm.step().is_running());
         }
+        assert_eq!(vm.writer, b"HiHiH");
 
-        let reader = BufReader::new(res.as_slice());
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(&mut vm, program);
+        vm.set_config(VmConfig {
+            max_output_bytes: Some(5),
+            output_overflow: OutputOverflow::Halt,
+            ..VmConfig::default()
+        });
+        assert_eq!(vm.resume(), RunResult::OutputLimitReached);
+        assert_eq!(vm.writer, b"HiHiH");
+        assert_eq!(vm.get_rpc(), 0x3002);
+    }
 
-        vm.load(reader);
+    #[test]
+    fn test_null_io() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.set_reader(b"z");
+        load_program(
+            &mut vm,
+            &[
+                0x3000,
+                0b1111000000100000, // getc
+                0b1111000000100001, // out
+                0b1111000000100011, // in
+                0b1111000000100101, // halt
+            ],
+        );
+        vm.set_config(VmConfig {
+            null_io: Some(b'x'),
+            ..VmConfig::default()
+        });
+        vm.run();
 
-        let nb_i = vm.run();
+        assert_eq!(vm.reg(Reg::R0), b'x' as u16);
+        assert!(vm.writer.is_empty());
+        assert_eq!(vm.reader, b"z");
+    }
+
+    #[test]
+    fn test_bus_mode() {
+        let program = &[
+            0x3000,
+            0b1010_000_000000010, // ldi r0 from xFE20
+            0b1111000000100101,   // halt
+            0b1111000000100101,   // halt
+            0xFE20,
+        ];
+
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(&mut vm, program);
+        vm.memory.mem[0xFE20] = 0x1234;
+        assert_eq!(vm.resume(), RunResult::Halted);
+        assert_eq!(vm.reg(Reg::R0), 0x1234);
+
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(&mut vm, program);
+        vm.memory.mem[0xFE20] = 0x1234;
+        vm.set_config(VmConfig {
+            bus_mode: BusMode::Strict,
+            ..VmConfig::default()
+        });
+        assert_eq!(vm.resume(), RunResult::Error(VmError::BusError(0xFE20)));
+        assert_eq!(vm.reg(Reg::R0), 0);
+    }
+
+    #[test]
+    fn test_supervisor_stack() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(&mut vm, &[0x3000, 0b1000_000000000000]); // rti
+        vm.set_supervisor_stack(0x2FF0..0x3000);
+        vm.set_reg(Reg::R6, 0x3000);
+        assert_eq!(vm.resume(), RunResult::Error(VmError::StackFault(0x3000)));
+        assert_eq!(vm.reg(Reg::R6), 0x3000);
+
+        // TRAP entry on a full stack
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(&mut vm, &[0x3000, 0b1111_0000_00110000]); // trap x30
+        vm.memory.mem[0x30] = 0x4000;
+        vm.set_config(VmConfig {
+            trap_return: TrapReturn::Rti,
+            ..VmConfig::default()
+        });
+        vm.set_supervisor_stack(0x2FFF..0x3000);
+        vm.set_reg(Reg::R6, 0x2FFF);
+        assert_eq!(vm.resume(), RunResult::Error(VmError::StackFault(0x2FFF)));
+        assert_eq!(vm.reg(Reg::R6), 0x2FFF);
+        assert_eq!(vm.memory.mem[0x2FFE], 0);
+    }
+
+    #[test]
+    fn test_trap_return() {
+        let program = &[
+            0x3000,
+            0b1111_0000_00110000,   // trap x30, installed at 0x3005
+            0b0001_001_001_1_00001, // add r1 and 1 in r1
+            0b1111000000100001,     // out
+            0b0001_001_001_1_00001, // add r1 and 1 in r1
+            0b1111000000100101,     // halt
+            0b0001_000_000_1_00001, // x3005: add r0 and 1 in r0
+            0b1100_000_111_000000,  // ret
+            0b1000_000000000000,    // rti
+        ];
+
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(&mut vm, program);
+        vm.memory.mem[0x30] = 0x3005;
+        assert_eq!(vm.resume(), RunResult::Halted);
+        assert_eq!(vm.reg(Reg::R0), 1);
+        assert_eq!(vm.reg(Reg::R1), 2);
+        assert_eq!(vm.reg(Reg::R7), 0x3003);
+
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(&mut vm, program);
+        vm.memory.mem[0x30] = 0x3005;
+        vm.memory.mem[0x3006] = 0; // no RET, fall through to RTI
+        vm.set_reg(Reg::R6, 0x4000);
+        vm.set_reg(Reg::R7, 0x1234);
+        vm.set_priority(2).unwrap();
+        vm.set_config(VmConfig {
+            trap_return: TrapReturn::Rti,
+            ..VmConfig::default()
+        });
+
+        vm.step();
+        assert_eq!(vm.reg(Reg::RPC), 0x3005);
+        assert_eq!(vm.reg(Reg::R6), 0x3FFE);
+        assert_eq!(vm.memory.mem[0x3FFE], 0x3001);
+        assert_eq!(vm.memory.mem[0x3FFF], 0x0202);
+        vm.set_priority(0).unwrap();
+
+        assert_eq!(vm.resume(), RunResult::Halted);
+        assert_eq!(vm.reg(Reg::R0), 1);
+        assert_eq!(vm.reg(Reg::R1), 2);
+        assert_eq!(vm.reg(Reg::R6), 0x4000);
+        assert_eq!(vm.reg(Reg::R7), 0x1234);
+        assert_eq!(vm.priority(), 2);
+    }
+
+    #[test]
+    fn test_clear_halt() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(
+            &mut vm,
+            &[
+                0x3000,
+                0b1111_0000_00100101,   // HALT
+                0b0001_000_000_1_00001, // ADD R0, R0, #1
+                0b1111_0000_00100101,   // HALT
+                0b0001_000_000_1_00010, // ADD R0, R0, #2
+                0b1111_0000_00100101,   // HALT
+            ],
+        );
+
+        vm.run();
+        assert!(vm.is_halted());
+        assert_eq!(vm.memory.read(MR_MCR) >> 15, 0);
+
+        vm.clear_halt();
+        assert!(!vm.is_halted());
+        assert_eq!(vm.memory.read(MR_MCR) >> 15, 1);
+        assert_eq!(vm.reg(Reg::RPC), 0x3001);
+        assert_eq!(vm.run(), 2);
+        assert_eq!(vm.reg(Reg::R0), 1);
+
+        vm.clear_halt();
+        vm.set_reg(Reg::RPC, 0x3003);
+        vm.run();
+        assert_eq!(vm.reg(Reg::R0), 3);
+    }
+
+    #[test]
+    fn test_uninstalled_trap() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(
+            &mut vm,
+            &[
+                0x3000,
+                0b1111_0000_00110000,   // TRAP x30
+                0b1111_0000_00110001,   // TRAP x31
+                0b1111_0000_00100101,   // HALT
+                0b0001_000_000_1_00001, // x3003: ADD R0, R0, #1
+                0b1100_000_111_000000,  // RET
+            ],
+        );
+        vm.memory.mem[0x31] = 0x3003;
+
+        assert_eq!(vm.resume(), RunResult::Error(VmError::BadTrap(0x30)));
+        assert_eq!(vm.reg(Reg::RPC), 0x3001);
+        assert_eq!(vm.reg(Reg::R7), 0);
+
+        assert_eq!(vm.resume(), RunResult::Halted);
+        assert_eq!(vm.reg(Reg::R0), 1);
+    }
+
+    #[test]
+    fn test_stepper() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(&mut vm, SUBROUTINE_PROGRAM);
+
+        let mut stepper = vm.stepper();
+        let mut pcs = Vec::new();
+        while let Some(step) = stepper.next() {
+            pcs.push(stepper.vm().get_rpc());
+            if step != StepResult::Continue {
+                assert_eq!(step, StepResult::Halted);
+            }
+        }
+        assert_eq!(pcs, vec![0x3003, 0x3004, 0x3005, 0x3001, 0x3002, 0x3003]);
+        assert!(stepper.next().is_none());
+
+        assert_eq!(vm.reg(Reg::R0), 6);
+        assert_eq!(vm.stepper().collect::<Vec<_>>(), vec![StepResult::Halted]);
+    }
+
+    #[test]
+    fn test_trace_n() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(&mut vm, SUBROUTINE_PROGRAM);
+
+        let trace = vm.trace_n(3);
+        assert_eq!(
+            trace,
+            vec![
+                (0x3000, String::from("JSR #2")),
+                (0x3003, String::from("ADD R0, R0, #5")),
+                (0x3004, String::from("ADD R0, R0, #1")),
+            ]
+        );
+
+        let trace = vm.trace_n(10);
+        assert_eq!(trace.len(), 3);
+        assert_eq!(trace[0], (0x3005, String::from("JMP R7")));
+        assert_eq!(trace[2], (0x3002, String::from("TRAP x25")));
+
+        assert_eq!(vm.trace_n(10), vec![]);
+
+        // The PC reaches a zero word past the image, it doesn't run
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(&mut vm, &[0x3000, 0b0001_000_000_1_00001]);
+        vm.set_config(VmConfig {
+            strict: true,
+            ..VmConfig::default()
+        });
+        assert_eq!(
+            vm.trace_n(10),
+            vec![(0x3000, String::from("ADD R0, R0, #1"))]
+        );
+
+        // Data isn't executed either
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(&mut vm, &[0x3000, 0b0001_000_000_1_00001]);
+        vm.mark_data([0x3000]);
+        vm.set_config(VmConfig {
+            strict_execution: true,
+            ..VmConfig::default()
+        });
+        assert_eq!(vm.trace_n(10), vec![]);
+    }
+
+    #[test]
+    fn test_set_writer() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(
+            &mut vm,
+            &[
+                0x3000,                 // start = 0x3000
+                0b0001_000_000_1_00111, // add r0 and 7 in r0
+                0b1111000000100111,     // out u16
+                0b1111000000100111,     // out u16
+                0b1111000000100101,     // halt
+            ],
+        );
+
+        vm.trace_n(2);
+        let old = vm.set_writer(Vec::new());
+        vm.run();
+
+        assert_eq!(old, b"7".to_vec());
+        assert_eq!(vm.writer, b"7".to_vec());
+    }
+
+    #[test]
+    fn test_set_reader() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        load_program(
+            &mut vm,
+            &[
+                0x3000,             // start = 0x3000
+                0b1111000000100000, // getc
+                0b1111000000100101, // halt
+            ],
+        );
+
+        vm.set_reader(b"A");
+        vm.run();
+
+        assert_eq!(vm.reg(Reg::R0), 0x41);
+    }
 
-        assert_eq!(vm.registers[&Reg::R0], 7);
-        assert_eq!(vm.registers[&Reg::R1], 1);
-        assert_eq!(vm.registers[&Reg::R2], 4);
-        assert_eq!(vm.registers[&Reg::R3], 0);
-        assert_eq!(vm.registers[&Reg::R4], 0);
-        assert_eq!(vm.registers[&Reg::R5], 718);
-        assert_eq!(vm.registers[&Reg::R6], 0);
-        assert_eq!(vm.registers[&Reg::R7], 4);
-        assert_eq!(nb_i, 7);
+    /// Load `program`, an origin followed by the words of the image
+    fn load_program(vm: &mut VM<&[u8], Vec<u8>>, program: &[u16]) {
+        let bytes = words_to_obj(program[0], &program[1..]);
+        vm.load(bytes.as_slice()).unwrap();
     }
 }
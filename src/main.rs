@@ -1,27 +1,164 @@
-use std::{env, fs::File, io::Stdout, time::Instant};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::{env, fs, fs::File, io, io::BufRead, io::Write, process, time::Instant};
 
-use toy_vm::{unsafe_zone, LibCReader, VM};
+use toy_vm::disassembler::disassemble;
+use toy_vm::{
+    assembler::assemble, mips, unsafe_zone, LibCReader, Register, RunResult, StepResult, VM,
+};
 
 fn main() {
     println!("Starting VM...");
 
-    unsafe_zone::disable_input_buffering();
+    let mut vm = VM::stdio();
+
+    // `--debug` can come anywhere, the remaining arguments are read as before
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let debug = args.iter().any(|a| a == "--debug");
+    args.retain(|a| a != "--debug");
+    let mut args = args.into_iter();
+    let first = args.next();
 
-    let mut vm: VM<LibCReader, Stdout> = VM::default();
+    if first.as_deref() == Some("--asm") {
+        // Assemble LC-3 source and run it directly, no .obj needed
+        let source_path = args.next().expect("--asm is followed by the source path");
+        let source = fs::read_to_string(&source_path).expect("Path exist");
+        let assembled = match assemble(&source) {
+            Ok(assembled) => assembled,
+            Err(e) => {
+                eprintln!("{source_path}: {e}");
+                process::exit(1);
+            }
+        };
+        vm.load_assembled(&assembled)
+            .expect("The program is a valid image");
+    } else if let Some(path) = first {
+        let f = File::open(path).expect("Path exist");
+        vm.load(f).expect("The program is a valid image");
+    } else {
+        // No path, e.g. `cat prog.obj | toy-vm`: the image is read from stdin
+        vm.load(io::stdin().lock())
+            .expect("The program is a valid image");
+    }
 
-    let mut args = env::args();
-    args.next();
-    let program_path = args.next().expect("The first argument is the program path");
+    let interrupt = unsafe_zone::catch_sigint();
+    vm.set_interrupt(interrupt.clone());
 
-    let f = File::open(program_path).expect("Path exist");
+    if debug {
+        let result = debug_session(&mut vm, &interrupt);
+        exit_on_fault(result);
+        return;
+    }
 
-    vm.load(f);
+    unsafe_zone::disable_input_buffering();
 
     let start = Instant::now();
-    let nb_instructions = vm.run();
+    let report = vm.run_report();
     let duration = start.elapsed();
 
-    println!("executed {nb_instructions} instructions in {:?}", duration);
-
     unsafe_zone::restore_input_buffering();
+    exit_on_fault(report.result);
+    let nb_instructions = report.instructions;
+
+    println!(
+        "executed {nb_instructions} instructions in {:?} ({:.2} MIPS)",
+        duration,
+        mips(nb_instructions, duration)
+    );
+}
+
+/// Exit with 130 after a Ctrl-C and with 1 after any other stop than a HALT. The terminal
+/// must already be restored
+fn exit_on_fault(result: RunResult) {
+    match result {
+        RunResult::Halted => {}
+        RunResult::Interrupted { pc } => {
+            eprintln!("interrupted at x{pc:04X}");
+            process::exit(130);
+        }
+        RunResult::Error(e) => {
+            eprintln!("error: {e}");
+            process::exit(1);
+        }
+        stop => {
+            eprintln!("stopped: {stop:?}");
+            process::exit(1);
+        }
+    }
+}
+
+/// A gdb-like prompt on stdin: `c` runs until a breakpoint or Ctrl-C, `s` steps over a trap,
+/// `r` dumps the registers, `b ADDR` sets a breakpoint, `x ADDR` reads memory and `q` quits.
+/// The terminal is in line mode at the prompt and in raw mode while the program runs.
+///
+/// Manual check: `toy-vm --debug 2048.obj`, `c`, press Ctrl-C, the prompt comes back with
+/// echo on; `c` again resumes the game.
+fn debug_session(vm: &mut VM<LibCReader, io::Stdout>, interrupt: &Arc<AtomicBool>) -> RunResult {
+    let stdin = io::stdin();
+    let mut line = String::new();
+    loop {
+        let pc = vm.register(Register::Pc);
+        eprint!("x{pc:04X}  {}\n(lc3) ", disassemble(vm.current_word()));
+        io::stderr().flush().ok();
+
+        line.clear();
+        if stdin.lock().read_line(&mut line).unwrap_or(0) == 0 {
+            return RunResult::Halted;
+        }
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or("");
+        let address = words.next().and_then(parse_address);
+
+        let stop = match command {
+            "c" | "continue" => {
+                // A Ctrl-C at the prompt mustn't stop the run right away
+                interrupt.store(false, Ordering::Relaxed);
+                unsafe_zone::disable_input_buffering();
+                let stop = vm.resume();
+                unsafe_zone::restore_input_buffering();
+                Some(stop)
+            }
+            "s" | "step" => match vm.debug_step() {
+                StepResult::Continue | StepResult::Trap(_) => None,
+                StepResult::Halted => Some(RunResult::Halted),
+                StepResult::Fault(fault) => Some(fault),
+            },
+            "r" | "regs" => {
+                println!("{}", vm.dump_registers());
+                None
+            }
+            "b" | "break" => {
+                match address {
+                    Some(addr) => vm.add_breakpoint(addr),
+                    None => eprintln!("usage: b ADDR"),
+                }
+                None
+            }
+            "x" => {
+                match address.map(|addr| vm.read_memory(addr)) {
+                    Some(Ok(word)) => println!("x{word:04X}"),
+                    Some(Err(e)) => eprintln!("error: {e}"),
+                    None => eprintln!("usage: x ADDR"),
+                }
+                None
+            }
+            "q" | "quit" => return RunResult::Halted,
+            "" => None,
+            other => {
+                eprintln!("unknown command {other}");
+                None
+            }
+        };
+        match stop {
+            None => {}
+            Some(RunResult::Interrupted { pc }) => eprintln!("interrupted at x{pc:04X}"),
+            Some(RunResult::Breakpoint { pc }) => eprintln!("breakpoint at x{pc:04X}"),
+            Some(stop) => return stop,
+        }
+    }
+}
+
+/// `x3000` or `3000`, in hexadecimal
+fn parse_address(text: &str) -> Option<u16> {
+    u16::from_str_radix(text.trim_start_matches(['x', 'X']), 16).ok()
 }
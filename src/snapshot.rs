@@ -0,0 +1,226 @@
+//! Saving and restoring the machine state as bytes, to persist a session.
+//!
+//! Layout, big-endian: the `LC3S` magic, a version word, the general purpose registers, PC,
+//! COND, PSR, the halt flag, the 65536 memory words and finally an FNV-1a checksum of
+//! everything before it. With the `extended-registers` feature the 16 registers make another
+//! layout, with its own version.
+
+use std::error::Error;
+use std::fmt;
+use std::io::{Read, Write};
+use std::ops::Range;
+
+use crate::{Reg, GPR_COUNT, VM};
+
+const MAGIC: &[u8; 4] = b"LC3S";
+/// Bumped whenever the layout changes
+#[cfg(not(feature = "extended-registers"))]
+const VERSION: u16 = 1;
+#[cfg(feature = "extended-registers")]
+const VERSION: u16 = 2;
+const HEADER_LEN: usize = MAGIC.len() + 2;
+/// Registers, PC, COND, PSR and halt, then the memory
+const WORDS: usize = GPR_COUNT as usize + 4 + u16::MAX as usize + 1;
+const GPRS: usize = GPR_COUNT as usize;
+const SNAPSHOT_LEN: usize = HEADER_LEN + WORDS * 2 + 4;
+
+/// Why a snapshot couldn't be restored, the VM is left untouched
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotError {
+    /// Not a snapshot, truncated or altered since it was taken
+    Corrupt,
+    /// Taken by a version of the crate with another layout
+    VersionMismatch { found: u16, expected: u16 },
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Corrupt => write!(f, "the snapshot is corrupt"),
+            SnapshotError::VersionMismatch { found, expected } => write!(
+                f,
+                "the snapshot has version {found}, version {expected} is expected"
+            ),
+        }
+    }
+}
+
+impl Error for SnapshotError {}
+
+fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c9dc5, |hash, b| {
+        (hash ^ *b as u32).wrapping_mul(0x01000193)
+    })
+}
+
+impl<R, W> VM<R, W>
+where
+    R: Read,
+    W: Write,
+{
+    /// Registers, halt flag and memory, to be given back to [`VM::restore`]
+    pub fn snapshot(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(SNAPSHOT_LEN);
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_be_bytes());
+
+        let mut words = Vec::with_capacity(WORDS);
+        words.extend((0..GPR_COUNT).map(|i| self.reg(Reg::from(i))));
+        words.push(self.reg(Reg::RPC));
+        words.push(self.reg(Reg::RCond));
+        words.push(self.psr);
+        words.push(self.halt as u16);
+        words.extend_from_slice(&self.memory.mem);
+        for word in words {
+            bytes.extend_from_slice(&word.to_be_bytes());
+        }
+
+        let sum = checksum(&bytes);
+        bytes.extend_from_slice(&sum.to_be_bytes());
+        bytes
+    }
+
+    /// Copy of the memory in `range`, bypassing the memory mapped devices
+    pub fn snapshot_region(&self, range: Range<u16>) -> Vec<u16> {
+        self.memory.mem[range.start as usize..range.end as usize].to_vec()
+    }
+
+    /// Write back a region taken by [`VM::snapshot_region`] at its `start` address, wrapping
+    /// around to x0000 past xFFFF
+    pub fn restore_region(&mut self, start: u16, words: &[u16]) {
+        for (i, word) in words.iter().enumerate() {
+            self.memory.mem[start.wrapping_add(i as u16) as usize] = *word;
+        }
+    }
+
+    /// Replace the state with one taken by [`VM::snapshot`].
+    /// The reader, writer, configuration and breakpoints are kept.
+    pub fn restore(&mut self, snapshot: &[u8]) -> Result<(), SnapshotError> {
+        if snapshot.len() < HEADER_LEN || &snapshot[..MAGIC.len()] != MAGIC {
+            return Err(SnapshotError::Corrupt);
+        }
+        let found = u16::from_be_bytes([snapshot[4], snapshot[5]]);
+        if found != VERSION {
+            return Err(SnapshotError::VersionMismatch {
+                found,
+                expected: VERSION,
+            });
+        }
+        if snapshot.len() != SNAPSHOT_LEN {
+            return Err(SnapshotError::Corrupt);
+        }
+        let (content, sum) = snapshot.split_at(SNAPSHOT_LEN - 4);
+        if checksum(content).to_be_bytes() != sum {
+            return Err(SnapshotError::Corrupt);
+        }
+
+        let words: Vec<u16> = content[HEADER_LEN..]
+            .chunks_exact(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+            .collect();
+        for i in 0..GPR_COUNT {
+            self.set_reg(Reg::from(i), words[i as usize]);
+        }
+        self.set_reg(Reg::RPC, words[GPRS]);
+        self.set_reg(Reg::RCond, words[GPRS + 1]);
+        self.psr = words[GPRS + 2];
+        self.halt = words[GPRS + 3] != 0;
+        self.memory.mem.copy_from_slice(&words[GPRS + 4..]);
+        self.call_stack.clear();
+        self.fault = None;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_roundtrip() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.set_reg(Reg::R3, 0xBEEF);
+        vm.set_reg(Reg::RPC, 0x4000);
+        vm.set_priority(3).unwrap();
+        vm.memory.mem[0x5000] = 0x1234;
+        let snapshot = vm.snapshot();
+
+        let mut restored = VM::<&[u8], Vec<u8>>::default();
+        assert_eq!(restored.restore(&snapshot), Ok(()));
+        assert_eq!(restored.reg(Reg::R3), 0xBEEF);
+        assert_eq!(restored.reg(Reg::RPC), 0x4000);
+        assert_eq!(restored.priority(), 3);
+        assert_eq!(restored.memory.mem[0x5000], 0x1234);
+        assert_eq!(restored.snapshot(), snapshot);
+    }
+
+    #[test]
+    fn test_snapshot_region() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.fill_mem(0x4000..0x4004, 7);
+        vm.memory.mem[0x4004] = 9;
+
+        let region = vm.snapshot_region(0x4002..0x4005);
+        assert_eq!(region, vec![7, 7, 9]);
+
+        vm.fill_mem(0x4000..0x4008, 1);
+        vm.restore_region(0x4002, &region);
+        assert_eq!(&vm.memory.mem[0x4000..0x4006], &[1, 1, 7, 7, 9, 1]);
+
+        vm.restore_region(0xFFFF, &region);
+        assert_eq!(vm.memory.mem[0xFFFF], 7);
+        assert_eq!(&vm.memory.mem[0x0000..0x0002], &[7, 9]);
+    }
+
+    #[test]
+    fn test_snapshot_corrupt() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.set_reg(Reg::R3, 0xBEEF);
+        let mut snapshot = vm.snapshot();
+
+        let mut other = VM::<&[u8], Vec<u8>>::default();
+        snapshot[HEADER_LEN + 7] ^= 1;
+        assert_eq!(other.restore(&snapshot), Err(SnapshotError::Corrupt));
+        assert_eq!(other.reg(Reg::R3), 0);
+
+        snapshot[HEADER_LEN + 7] ^= 1;
+        assert_eq!(
+            other.restore(&snapshot[..snapshot.len() - 1]),
+            Err(SnapshotError::Corrupt)
+        );
+        assert_eq!(
+            other.restore(b"not a snapshot"),
+            Err(SnapshotError::Corrupt)
+        );
+
+        snapshot[5] = 9;
+        assert_eq!(
+            other.restore(&snapshot),
+            Err(SnapshotError::VersionMismatch {
+                found: 9,
+                expected: VERSION
+            })
+        );
+    }
+
+    #[test]
+    fn test_snapshot_other_register_count() {
+        // A snapshot taken with or without the extended-registers feature, whichever this
+        // build isn't
+        let (version, gprs) = if GPR_COUNT == 8 { (2, 16) } else { (1, 8) };
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&u16::to_be_bytes(version));
+        bytes.resize(HEADER_LEN + (gprs + 4 + 0x10000) * 2, 0);
+        let sum = checksum(&bytes);
+        bytes.extend_from_slice(&sum.to_be_bytes());
+
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        assert_eq!(
+            vm.restore(&bytes),
+            Err(SnapshotError::VersionMismatch {
+                found: version,
+                expected: VERSION
+            })
+        );
+    }
+}
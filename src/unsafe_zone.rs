@@ -1,16 +1,27 @@
 #![allow(non_camel_case_types)]
+use std::ptr::{addr_of, addr_of_mut};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+
 pub type c_int = i32;
 pub type c_uchar = u8;
 pub type c_uint = u32;
+pub type c_ulong = u64;
+pub type c_ushort = u16;
 pub type cc_t = c_uchar;
 pub type speed_t = c_uint;
 pub type tcflag_t = c_uint;
 
 const STDIN_FILENO: i32 = 0;
+const STDOUT_FILENO: i32 = 1;
+pub const TIOCGWINSZ: c_ulong = 0x5413;
 pub const ICANON: tcflag_t = 0x00000002;
 pub const ECHO: tcflag_t = 0x00000008;
 pub const TCSANOW: c_int = 0;
 pub const NCCS: usize = 32;
+const SIGINT: c_int = 2;
+
+static INTERRUPT: OnceLock<Arc<AtomicBool>> = OnceLock::new();
 
 static mut ORIGINAL_TIO: termios = termios {
     c_iflag: 0,
@@ -38,22 +49,68 @@ pub struct termios {
 
 pub fn disable_input_buffering() {
     unsafe {
-        tcgetattr(STDIN_FILENO, &mut ORIGINAL_TIO);
+        tcgetattr(STDIN_FILENO, addr_of_mut!(ORIGINAL_TIO));
         let mut new_tio = ORIGINAL_TIO;
-        new_tio.c_lflag = new_tio.c_lflag & (!ICANON & !ECHO);
+        new_tio.c_lflag &= !ICANON & !ECHO;
         tcsetattr(STDIN_FILENO, TCSANOW, &new_tio);
     };
 }
 
 pub fn restore_input_buffering() {
-    unsafe { tcsetattr(STDIN_FILENO, TCSANOW, &ORIGINAL_TIO) };
+    unsafe { tcsetattr(STDIN_FILENO, TCSANOW, addr_of!(ORIGINAL_TIO)) };
 }
 
-pub fn get_char() -> u8 {
-    unsafe {
-        let c = getchar();
-        return c as u8;
-    };
+/// Returned by [`get_char`] once stdin is exhausted
+pub const EOF: c_int = -1;
+
+/// Next byte of stdin as an `unsigned char` widened to `c_int`, or [`EOF`]
+pub fn get_char() -> c_int {
+    unsafe { getchar() }
+}
+
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+#[repr(C)]
+pub struct winsize {
+    pub ws_row: c_ushort,
+    pub ws_col: c_ushort,
+    pub ws_xpixel: c_ushort,
+    pub ws_ypixel: c_ushort,
+}
+
+/// Size of the terminal as `(columns, rows)`, `None` when stdout is not a terminal.
+///
+/// The VM doesn't catch SIGWINCH (its default action is to be ignored, so a resize can't
+/// kill it nor leave raw mode), a renderer should query the size again before each frame.
+/// Manual check: run a program drawing the framebuffer and resize the terminal.
+pub fn terminal_size() -> Option<(u16, u16)> {
+    let mut size = winsize::default();
+    let result = unsafe { ioctl(STDOUT_FILENO, TIOCGWINSZ, addr_of_mut!(size)) };
+    if result == 0 && size.ws_col > 0 && size.ws_row > 0 {
+        Some((size.ws_col, size.ws_row))
+    } else {
+        None
+    }
+}
+
+extern "C" fn on_sigint(_signal: c_int) {
+    if let Some(flag) = INTERRUPT.get() {
+        flag.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Catch SIGINT and raise the returned flag instead of killing the process, give it to
+/// [`crate::VM::set_interrupt`] so Ctrl-C stops the VM and the terminal can be restored.
+///
+/// The handler is installed once, later calls return the same flag. A VM blocked reading a
+/// key only sees the flag after the next key. Manual check: run a looping program, press
+/// Ctrl-C and type in the shell, the typed characters must be echoed again.
+pub fn catch_sigint() -> Arc<AtomicBool> {
+    INTERRUPT
+        .get_or_init(|| {
+            unsafe { signal(SIGINT, on_sigint) };
+            Arc::new(AtomicBool::new(false))
+        })
+        .clone()
 }
 
 #[link(name = "c")]
@@ -61,4 +118,27 @@ extern "C" {
     pub fn tcgetattr(fd: c_int, termios_p: *mut termios) -> c_int;
     pub fn tcsetattr(fd: c_int, optional_actions: c_int, termios_p: *const termios) -> c_int;
     pub fn getchar() -> c_int;
+    pub fn ioctl(fd: c_int, request: c_ulong, ...) -> c_int;
+    pub fn signal(signum: c_int, handler: extern "C" fn(c_int)) -> usize;
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_terminal_size() {
+        // Under cargo test stdout is usually captured, so only check the answer is consistent
+        if let Some((columns, rows)) = terminal_size() {
+            assert!(columns > 0);
+            assert!(rows > 0);
+        }
+    }
+
+    #[test]
+    fn test_catch_sigint() {
+        let flag = catch_sigint();
+        assert!(Arc::ptr_eq(&flag, &catch_sigint()));
+    }
 }
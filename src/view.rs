@@ -0,0 +1,82 @@
+//! Read-only access to a VM, for embedders exposing its state to code that must not change it.
+
+use std::io::{Read, Write};
+use std::ops::Range;
+
+use crate::{cc_str, Reg, GPR_COUNT, REG_COUNT, VM};
+
+/// A VM borrowed for inspection only, see [`VM::view`]
+#[derive(Clone, Copy)]
+pub struct VmView<'a> {
+    memory: &'a [u16],
+    registers: &'a [u16; REG_COUNT],
+    psr: u16,
+}
+
+impl<R, W> VM<R, W>
+where
+    R: Read,
+    W: Write,
+{
+    pub fn view(&self) -> VmView<'_> {
+        VmView {
+            memory: &self.memory.mem,
+            registers: &self.registers,
+            psr: self.psr(),
+        }
+    }
+}
+
+impl VmView<'_> {
+    /// General purpose register `n`, `None` past the last one
+    pub fn register(&self, n: u16) -> Option<u16> {
+        (n < GPR_COUNT).then(|| self.registers[Reg::from(n) as usize])
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.registers[Reg::RPC as usize]
+    }
+
+    /// Condition code, as [`VM::cc_string`]
+    pub fn cc(&self) -> &'static str {
+        cc_str(self.registers[Reg::RCond as usize])
+    }
+
+    /// Processor status register, as [`VM::psr`]
+    pub fn psr(&self) -> u16 {
+        self.psr
+    }
+
+    /// Word at `address` as stored, the devices aren't polled
+    pub fn read(&self, address: u16) -> u16 {
+        self.memory[address as usize]
+    }
+
+    pub fn memory(&self, range: Range<u16>) -> &[u16] {
+        &self.memory[range.start as usize..range.end as usize]
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unusual_byte_groupings)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_view() {
+        let mut vm = VM::<&[u8], Vec<u8>>::default();
+        vm.load(&crate::words_to_obj(0x3000, &[0b0001_001_001_1_11111, 0xF025])[..])
+            .unwrap();
+        vm.step();
+
+        let view = vm.view();
+        assert_eq!(view.register(1), Some(0xFFFF));
+        assert_eq!(view.register(GPR_COUNT), None);
+        assert_eq!(view.pc(), 0x3001);
+        assert_eq!(view.cc(), "N");
+        assert_eq!(view.psr(), 0b100);
+        assert_eq!(view.read(0x3001), 0xF025);
+        assert_eq!(view.memory(0x3000..0x3002), &[0x127F, 0xF025]);
+    }
+}
@@ -0,0 +1,43 @@
+//! Runs the binary in `--asm` mode on the fixtures in `tests/fixtures`
+
+use std::process::Command;
+
+fn run_asm(fixture: &str) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_toy-vm"))
+        .arg("--asm")
+        .arg(format!(
+            "{}/tests/fixtures/{fixture}",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .output()
+        .expect("The binary runs")
+}
+
+#[test]
+fn test_asm_mode_runs_source() {
+    let output = run_asm("hello.asm");
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Hello, LC-3!"), "stdout: {stdout}");
+}
+
+#[test]
+fn test_asm_mode_reports_errors() {
+    let output = run_asm("bad.asm");
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("line 3"), "stderr: {stderr}");
+}
+
+#[test]
+fn test_asm_mode_reports_faults() {
+    let output = run_asm("fault.asm");
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("trap x99"), "stderr: {stderr}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!stdout.contains("executed"), "stdout: {stdout}");
+}
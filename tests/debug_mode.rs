@@ -0,0 +1,81 @@
+//! Runs the binary with `--debug`, driving the prompt through stdin
+
+use std::io::{Read, Write};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+fn spawn_debug(fixture: &str, commands: &str) -> Child {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_toy-vm"))
+        .arg("--debug")
+        .arg("--asm")
+        .arg(format!(
+            "{}/tests/fixtures/{fixture}",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("The binary runs");
+    child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(commands.as_bytes())
+        .expect("The commands are piped");
+    child
+}
+
+#[test]
+fn test_debug_breakpoint_and_step() {
+    let child = spawn_debug("hello.asm", "b x3001\nc\nr\ns\nc\n");
+    let output = child.wait_with_output().expect("The binary exits");
+
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("breakpoint at x3001"), "stderr: {stderr}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("R0=x3003"), "stdout: {stdout}");
+    assert!(stdout.contains("Hello, LC-3!"), "stdout: {stdout}");
+}
+
+#[test]
+fn test_debug_sigint_returns_to_prompt() {
+    let mut child = spawn_debug("loop.asm", "c\nr\nq\n");
+
+    // SIGINT is only caught once the first prompt is shown
+    let mut stderr = child.stderr.take().expect("stderr is piped");
+    let mut seen = Vec::new();
+    let mut byte = [0];
+    while !seen.ends_with(b"(lc3) ") && stderr.read(&mut byte).expect("stderr is readable") == 1 {
+        seen.push(byte[0]);
+    }
+
+    // The signal may come before the run starts and be dropped, so send it until the prompt
+    // has read `q`
+    let status = loop {
+        if let Some(status) = child.try_wait().expect("The status is readable") {
+            break status;
+        }
+        Command::new("kill")
+            .arg("-INT")
+            .arg(child.id().to_string())
+            .status()
+            .expect("kill runs");
+        thread::sleep(Duration::from_millis(100));
+    };
+    stderr.read_to_end(&mut seen).expect("stderr is readable");
+    let mut stdout = String::new();
+    child
+        .stdout
+        .take()
+        .expect("stdout is piped")
+        .read_to_string(&mut stdout)
+        .expect("stdout is readable");
+
+    assert!(status.success());
+    let stderr = String::from_utf8_lossy(&seen);
+    assert!(stderr.contains("interrupted at x3000"), "stderr: {stderr}");
+    assert!(stdout.contains("PC=x3000"), "stdout: {stdout}");
+}
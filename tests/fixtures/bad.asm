@@ -0,0 +1,4 @@
+        .ORIG x3000
+        ADD R0, R0, #1
+        FOO R1
+        .END
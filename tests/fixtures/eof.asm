@@ -0,0 +1,14 @@
+; Reads a key and tells whether the input was exhausted, used by tests/stdin_eof.rs
+        .ORIG x3000
+        GETC
+        ADD R0, R0, #0
+        BRnp KEY
+        LEA R0, EOF
+        PUTS
+        HALT
+KEY     LEA R0, GOT
+        PUTS
+        HALT
+EOF     .STRINGZ "end of input"
+GOT     .STRINGZ "got a key"
+        .END
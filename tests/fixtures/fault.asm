@@ -0,0 +1,5 @@
+; Runs one instruction then an uninstalled trap, used by tests/asm_mode.rs
+        .ORIG x3000
+        ADD R0, R0, #1
+        TRAP x99
+        .END
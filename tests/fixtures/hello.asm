@@ -0,0 +1,7 @@
+; Prints a greeting, used by tests/asm_mode.rs
+        .ORIG x3000
+        LEA R0, GREETING
+        TRAP x22
+        TRAP x25
+GREETING .STRINGZ "Hello, LC-3!"
+        .END
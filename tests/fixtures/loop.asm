@@ -0,0 +1,4 @@
+; Loops forever, used by tests/debug_mode.rs
+        .ORIG x3000
+LOOP    BR LOOP
+        .END
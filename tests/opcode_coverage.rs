@@ -0,0 +1,88 @@
+//! Checks that the decoder is exercised for every opcode and trap vector.
+//!
+//! When adding an opcode or a trap, extend `PROGRAM` so it executes the new instruction
+//! and, for a trap, add its vector to `TRAPS`. An opcode the VM deliberately leaves
+//! unimplemented goes in `UNIMPLEMENTED_OPCODES` instead, with the reason.
+
+use toy_vm::assembler::assemble;
+use toy_vm::VM;
+
+/// Opcodes that stop the VM when executed, so the program can't go on past them
+const UNIMPLEMENTED_OPCODES: &[usize] = &[
+    0b1101, // reserved
+];
+
+/// Every trap vector the decoder knows about
+const TRAPS: &[u8] = &[
+    0x20, 0x21, 0x22, 0x23, 0x24, 0x25, 0x26, 0x27, 0x28, 0x2C, 0x2D, 0x2E, 0x2F,
+];
+
+const PROGRAM: &str = r#"
+        .ORIG x3000
+        LEA R6, DATA
+        AND R1, R1, #0
+        ADD R1, R1, #3
+        NOT R2, R1
+        LD R3, VALUE
+        ST R3, SCRATCH
+        LDR R4, R6, #0
+        STR R4, R6, #1
+        LDI R5, POINTER
+        STI R5, POINTER
+        JSR SUB
+        LEA R0, SUB2
+        JSRR R0
+        LEA R6, FRAME
+        RTI
+AFTER   BRnzp TRAPS
+SUB     JMP R7
+SUB2    JMP R7
+TRAPS   TRAP x20
+        TRAP x21
+        LEA R0, TEXT
+        TRAP x22
+        TRAP x23
+        LEA R0, EMPTY
+        TRAP x24
+        TRAP x26
+        TRAP x27
+        TRAP x28
+        TRAP x2C
+        TRAP x2D
+        TRAP x2E
+        TRAP x2F
+        TRAP x25
+DATA    .FILL x1234
+SCRATCH .FILL #0
+VALUE   .FILL #7
+POINTER .FILL SCRATCH
+TEXT    .STRINGZ "hi"
+EMPTY   .FILL #0
+FRAME   .FILL AFTER
+        .FILL x0002
+        .END
+"#;
+
+#[test]
+fn test_every_opcode_and_trap_is_decoded() {
+    let assembled = assemble(PROGRAM).expect("The coverage program assembles");
+    let image = assembled.to_bytes();
+
+    let mut vm = VM::<&[u8], Vec<u8>>::default();
+    vm.load(&image[..]).expect("The coverage program loads");
+    vm.set_reader(b"ab42\n-7\n");
+    vm.run();
+
+    for (opcode, count) in vm.opcode_histogram().iter().enumerate() {
+        if UNIMPLEMENTED_OPCODES.contains(&opcode) {
+            continue;
+        }
+        assert!(*count > 0, "opcode {opcode:04b} was never executed");
+    }
+    for trap in TRAPS {
+        assert!(
+            vm.trap_histogram().contains_key(trap),
+            "trap x{trap:02X} was never executed"
+        );
+    }
+}
@@ -0,0 +1,67 @@
+//! Canonical end-to-end smoke test: assemble a looping program, run it to HALT without a
+//! terminal and check its result. It covers the interplay of ADD, BR and the condition codes
+//! that the per-instruction unit tests don't.
+//! A second program checks the assembler pseudo-ops run as the instructions they stand for.
+
+use toy_vm::assembler::assemble;
+use toy_vm::{RunResult, VM};
+
+const SUM: &str = r#"
+; R0 = 1 + 2 + ... + 10
+        .ORIG x3000
+        AND R0, R0, #0
+        AND R1, R1, #0
+        ADD R1, R1, #10
+LOOP    ADD R0, R0, R1
+        ADD R1, R1, #-1
+        BRp LOOP
+        TRAP x25
+        .END
+"#;
+
+#[test]
+fn test_sum_loop() {
+    let assembled = assemble(SUM).expect("The program assembles");
+    let mut vm = VM::<&[u8], Vec<u8>>::default();
+    vm.load_assembled(&assembled).expect("The program loads");
+
+    let report = vm.run_report();
+
+    assert_eq!(report.result, RunResult::Halted);
+    assert_eq!(report.r0, 55);
+    assert_eq!(report.instructions, 3 + 10 * 3 + 1);
+    assert!(vm.assert_output(b"").is_ok());
+}
+
+const ECHO: &str = r#"
+; echo one character between two strings, through a subroutine
+        .ORIG x3000
+        LEA R0, HELLO
+        PUTS
+        JSR ECHO
+        NOP
+        HALT
+ECHO    ST R7, SAVE
+        GETC
+        OUT
+        LD R7, SAVE
+        RET
+SAVE    .BLKW 1
+HELLO   .STRINGZ "> "
+        .END
+"#;
+
+#[test]
+fn test_pseudo_ops() {
+    let assembled = assemble(ECHO).expect("The program assembles");
+    let mut vm = VM::<&[u8], Vec<u8>>::default();
+    vm.set_reader(b"x");
+    vm.load_assembled(&assembled).expect("The program loads");
+
+    let report = vm.run_report();
+
+    assert_eq!(report.result, RunResult::Halted);
+    assert_eq!(report.r0, u16::from(b'x'));
+    assert_eq!(report.instructions, 10);
+    assert!(vm.assert_output(b"> x").is_ok());
+}
@@ -0,0 +1,20 @@
+//! Runs the binary with an empty stdin, GETC must see the end of the input
+
+use std::process::{Command, Stdio};
+
+#[test]
+fn test_empty_stdin_is_eof() {
+    let output = Command::new(env!("CARGO_BIN_EXE_toy-vm"))
+        .arg("--asm")
+        .arg(format!(
+            "{}/tests/fixtures/eof.asm",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .stdin(Stdio::null())
+        .output()
+        .expect("The binary runs");
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("end of input"), "stdout: {stdout}");
+}
@@ -0,0 +1,30 @@
+//! Runs the binary without a path, piping the image on stdin
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+#[test]
+fn test_image_from_stdin() {
+    let image = std::fs::read(format!(
+        "{}/lc3-tools/hello_world.obj",
+        env!("CARGO_MANIFEST_DIR")
+    ))
+    .expect("The sample image exists");
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_toy-vm"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("The binary runs");
+    child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(&image)
+        .expect("The image is piped");
+    let output = child.wait_with_output().expect("The binary exits");
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Hello World"), "stdout: {stdout}");
+}